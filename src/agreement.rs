@@ -0,0 +1,299 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::dataset::{Dataset, Label, YoloBB};
+use crate::diff::{class_name, diff_labels};
+use crate::matching::{Assignment, Matcher};
+
+#[derive(Serialize, Debug)]
+struct Counts {
+    /// Minimal IoU of a match
+    iou: f32,
+    /// Boxes of `a` matched to a box of `b` of the same class
+    matched: usize,
+    /// Matched boxes of `a` per box of `a`
+    precision: Option<f32>,
+    /// Matched boxes of `b` per box of `b`
+    recall: Option<f32>,
+}
+
+#[derive(Serialize, Debug)]
+struct ClassAgreement {
+    class: String,
+    boxes_a: usize,
+    boxes_b: usize,
+    thresholds: Vec<Counts>,
+    /// Mean IoU of the matches at the lowest threshold
+    mean_iou: Option<f32>,
+}
+
+#[derive(Serialize, Debug)]
+struct DisputedImage {
+    image: String,
+    boxes_a: usize,
+    boxes_b: usize,
+    /// Matches at the lowest threshold
+    matched: usize,
+    /// Share of the boxes of both without a match at the lowest threshold
+    disagreement: f32,
+}
+
+#[derive(Serialize, Debug)]
+struct Agreement {
+    a: String,
+    b: String,
+    /// Images labeled in both directories
+    images: usize,
+    overall: ClassAgreement,
+    classes: Vec<ClassAgreement>,
+    disputed: Vec<DisputedImage>,
+}
+
+/// Box and match counts of one class, or of all.
+#[derive(Default)]
+struct Tally {
+    boxes_a: usize,
+    boxes_b: usize,
+    matched: Vec<usize>,
+    iou_sum: f32,
+}
+
+impl Tally {
+    fn agreement(&self, class: String, thresholds: &[f32]) -> ClassAgreement {
+        let ratio = |n: usize, of: usize| (of > 0).then(|| n as f32 / of as f32);
+        ClassAgreement {
+            class,
+            boxes_a: self.boxes_a,
+            boxes_b: self.boxes_b,
+            thresholds: thresholds
+                .iter()
+                .zip(&self.matched)
+                .map(|(iou, matched)| Counts {
+                    iou: *iou,
+                    matched: *matched,
+                    precision: ratio(*matched, self.boxes_a),
+                    recall: ratio(*matched, self.boxes_b),
+                })
+                .collect(),
+            mean_iou: (self.matched[0] > 0).then(|| self.iou_sum / self.matched[0] as f32),
+        }
+    }
+}
+
+/// Counts the boxes of `a` and `b` and the ones matched with the same class by each of
+/// `matchers` into the tallies of their class and `overall`. Returns the matches of the first.
+fn tally<L: Label>(
+    a: &[YoloBB<L>],
+    b: &[YoloBB<L>],
+    matchers: &[Matcher],
+    classes: &mut [Tally],
+    overall: &mut Tally,
+) -> usize {
+    for bb in a {
+        classes[bb.class_id()].boxes_a += 1;
+    }
+    for bb in b {
+        classes[bb.class_id()].boxes_b += 1;
+    }
+    overall.boxes_a += a.len();
+    overall.boxes_b += b.len();
+
+    let mut first = 0;
+    for (t, matcher) in matchers.iter().enumerate() {
+        // every match of the same class counts, however far it moved
+        let diff = diff_labels(a, b, matcher, 0.0);
+        for m in &diff.unchanged {
+            let class = &mut classes[a[m.a].class_id()];
+            class.matched[t] += 1;
+            if t == 0 {
+                class.iou_sum += m.iou;
+                overall.iou_sum += m.iou;
+            }
+        }
+        overall.matched[t] += diff.unchanged.len();
+        if t == 0 {
+            first = diff.unchanged.len();
+        }
+    }
+    first
+}
+
+/// Measures how well the labels of annotator `a` agree with the ones of annotator `b` on the
+/// images both labeled: precision and recall of `a` against `b` per class at every IoU of
+/// `thresholds`, the mean IoU of the matched boxes and the `disputed` images with the most
+/// unmatched boxes. Prints a summary and writes everything as JSON to `report`. Boxes of
+/// classes unknown to the label set are left out.
+pub fn agreement<L: Label>(
+    a: &Dataset<L>,
+    b: &Dataset<L>,
+    thresholds: &[f32],
+    assignment: Assignment,
+    disputed: usize,
+    report: &Path,
+) -> Result<()> {
+    let mut thresholds = thresholds.to_vec();
+    thresholds.sort_by(f32::total_cmp);
+    thresholds.dedup();
+    if thresholds.is_empty() {
+        bail!("no IoU thresholds");
+    }
+    let matchers: Vec<_> = thresholds
+        .iter()
+        .map(|iou| Matcher::new(*iou, assignment))
+        .collect();
+
+    let new_tally = || Tally {
+        matched: vec![0; thresholds.len()],
+        ..Default::default()
+    };
+    let mut classes: Vec<Tally> = L::all().iter().map(|_| new_tally()).collect();
+    let mut overall = new_tally();
+    let (mut compared, mut images) = (0, vec![]);
+    for i in (0..a.len()).filter(|i| a.has_label(*i) && b.has_label(*i)) {
+        compared += 1;
+        let (mut a_label, mut b_label) = (a.label(i)?, b.label(i)?);
+        a_label.retain(|bb| bb.class_id() < classes.len());
+        b_label.retain(|bb| bb.class_id() < classes.len());
+        let matched = tally(&a_label, &b_label, &matchers, &mut classes, &mut overall);
+
+        let boxes = a_label.len() + b_label.len();
+        if boxes > 2 * matched {
+            images.push(DisputedImage {
+                image: a
+                    .image_path(i)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                boxes_a: a_label.len(),
+                boxes_b: b_label.len(),
+                matched,
+                disagreement: 1.0 - 2.0 * matched as f32 / boxes as f32,
+            });
+        }
+    }
+    if compared == 0 {
+        bail!("no image is labeled in both directories");
+    }
+    images.sort_by(|x, y| {
+        y.disagreement
+            .total_cmp(&x.disagreement)
+            .then_with(|| x.image.cmp(&y.image))
+    });
+    images.truncate(disputed);
+
+    let agreement = Agreement {
+        a: a.label_path(0).parent().unwrap().display().to_string(),
+        b: b.label_path(0).parent().unwrap().display().to_string(),
+        images: compared,
+        overall: overall.agreement("all".into(), &thresholds),
+        classes: classes
+            .iter()
+            .enumerate()
+            .filter(|(_, tally)| tally.boxes_a + tally.boxes_b > 0)
+            .map(|(class, tally)| tally.agreement(class_name::<L>(class), &thresholds))
+            .collect(),
+        disputed: images,
+    };
+
+    let percent = |ratio: Option<f32>| match ratio {
+        Some(ratio) => format!("{:>6.1}%", 100.0 * ratio),
+        None => format!("{:>7}", "-"),
+    };
+    println!("{compared} images labeled in both");
+    for class in agreement.classes.iter().chain([&agreement.overall]) {
+        print!(
+            "{:>12}: {:>5} a, {:>5} b",
+            class.class, class.boxes_a, class.boxes_b
+        );
+        for counts in &class.thresholds {
+            print!(
+                ", IoU {:.2}: precision {} recall {}",
+                counts.iou,
+                percent(counts.precision),
+                percent(counts.recall)
+            );
+        }
+        match class.mean_iou {
+            Some(iou) => println!(", mean IoU {iou:.2}"),
+            None => println!(),
+        }
+    }
+    for image in &agreement.disputed {
+        println!(
+            "Disputed {}: {} of {} boxes of a and {} of b matched",
+            image.image, image.matched, image.boxes_a, image.boxes_b
+        );
+    }
+
+    let file = std::fs::File::create(report)
+        .with_context(|| format!("could not create report {report:?}"))?;
+    serde_json::to_writer_pretty(file, &agreement)?;
+    println!("Wrote agreement report to {report:?}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Card;
+
+    fn label(lines: &[&str]) -> Vec<YoloBB<Card>> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn ratios(agreement: &ClassAgreement) -> Vec<(Option<f32>, Option<f32>)> {
+        agreement
+            .thresholds
+            .iter()
+            .map(|counts| (counts.precision, counts.recall))
+            .collect()
+    }
+
+    #[test]
+    fn precision_and_recall_per_threshold() {
+        let thresholds = [0.5, 0.75];
+        let matchers: Vec<_> = thresholds
+            .iter()
+            .map(|iou| Matcher::new(*iou, Assignment::Hungarian))
+            .collect();
+        let new_tally = || Tally {
+            matched: vec![0; thresholds.len()],
+            ..Default::default()
+        };
+        let mut classes: Vec<Tally> = Card::all().iter().map(|_| new_tally()).collect();
+        let mut overall = new_tally();
+        // A is the same, K moved to an IoU of 0.6, Q is only in a and J only in b
+        let a = label(&[
+            "0 0.2 0.2 0.1 0.1",
+            "1 0.5 0.5 0.2 0.2",
+            "2 0.8 0.8 0.1 0.1",
+        ]);
+        let b = label(&[
+            "0 0.2 0.2 0.1 0.1",
+            "1 0.55 0.5 0.2 0.2",
+            "3 0.1 0.9 0.1 0.1",
+        ]);
+        let matched = tally(&a, &b, &matchers, &mut classes, &mut overall);
+        assert_eq!(matched, 2);
+
+        let third = Some(1.0 / 3.0);
+        let overall = overall.agreement("all".into(), &thresholds);
+        assert_eq!(
+            ratios(&overall),
+            [(Some(2.0 / 3.0), Some(2.0 / 3.0)), (third, third)]
+        );
+        assert!((overall.mean_iou.unwrap() - 0.8).abs() < 1e-5);
+
+        let class = |id: usize| classes[id].agreement(class_name::<Card>(id), &thresholds);
+        assert_eq!(ratios(&class(0)), [(Some(1.0), Some(1.0)); 2]);
+        assert_eq!(
+            ratios(&class(1)),
+            [(Some(1.0), Some(1.0)), (Some(0.0), Some(0.0))]
+        );
+        assert_eq!(ratios(&class(2)), [(Some(0.0), None); 2]);
+        assert_eq!(ratios(&class(3)), [(None, Some(0.0)); 2]);
+        assert_eq!(class(4).mean_iou, None);
+    }
+}
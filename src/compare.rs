@@ -0,0 +1,256 @@
+use eframe::egui;
+use egui::*;
+use std::collections::HashMap;
+
+use crate::dataset::{BoundingBox, Dataset, Label, YoloLabel};
+use crate::diff::{self, LabelDiff};
+use crate::keymap::{self, Action, Bindings};
+use crate::matching::Matcher;
+use crate::widgets;
+
+/// Shows the labels of two label directories of the same images on top of each other, the
+/// boxes of `a` solid and the boxes of `b` dashed, with the differing boxes emphasized.
+pub struct Compare<L: Label> {
+    image_texture: egui::TextureHandle,
+    image_rect: Rect,
+    a: Dataset<L>,
+    b: Dataset<L>,
+    a_label: YoloLabel<L>,
+    b_label: YoloLabel<L>,
+    diff: LabelDiff,
+    matcher: Matcher,
+    moved_iou: f32,
+    // only step through images whose labels differ
+    only_differing: bool,
+    search_query: String,
+    bindings: Bindings,
+    show_cheat_sheet: bool,
+    // why the labels of the current image could not be read
+    notice: Option<String>,
+}
+
+impl<L: Label> Compare<L> {
+    pub fn build_app(
+        cc: &eframe::CreationContext<'_>,
+        bindings: Bindings,
+        mut a: Dataset<L>,
+        mut b: Dataset<L>,
+        matcher: Matcher,
+        moved_iou: f32,
+    ) -> Box<dyn eframe::App> {
+        // both start at the first image, not at their first unlabeled ones
+        a.goto(0);
+        b.goto(0);
+        let image = a.current_image().unwrap();
+        let image_texture =
+            cc.egui_ctx
+                .load_texture("my-image", image, egui::TextureFilter::Linear);
+        let mut compare = Compare {
+            image_texture,
+            image_rect: Rect::NOTHING,
+            a,
+            b,
+            a_label: vec![],
+            b_label: vec![],
+            diff: LabelDiff::default(),
+            matcher,
+            moved_iou,
+            only_differing: true,
+            search_query: String::new(),
+            bindings,
+            show_cheat_sheet: false,
+            notice: None,
+        };
+        compare.load_labels();
+        if compare.diff.is_empty() {
+            compare.step(true);
+        }
+        compare.update_texture(&cc.egui_ctx);
+        Box::new(compare)
+    }
+    /// Reads the labels of the current image without creating missing ones, an unreadable label
+    /// is shown as empty with the error as notice.
+    fn load_labels(&mut self) {
+        let (_, i, _) = self.a.get_progress();
+        let mut errors = vec![];
+        let mut read = |dataset: &Dataset<L>| {
+            dataset.label(i).unwrap_or_else(|e| {
+                errors.push(format!("{:?}: {e:#}", dataset.label_path(i)));
+                vec![]
+            })
+        };
+        self.a_label = read(&self.a);
+        self.b_label = read(&self.b);
+        self.notice = (!errors.is_empty()).then(|| errors.join("\n"));
+        self.diff = diff::diff_labels(&self.a_label, &self.b_label, &self.matcher, self.moved_iou);
+    }
+    /// Whether the labels of the `i`-th image differ, an unreadable one always does.
+    fn differs(&self, i: usize) -> bool {
+        match (self.a.label(i), self.b.label(i)) {
+            (Ok(a), Ok(b)) => !diff::diff_labels(&a, &b, &self.matcher, self.moved_iou).is_empty(),
+            _ => true,
+        }
+    }
+    fn update_texture(&mut self, ctx: &Context) {
+        let image = self.a.current_image().unwrap();
+        self.image_texture = ctx.load_texture("my-image", image, egui::TextureFilter::Linear);
+    }
+    fn seek(&mut self, i: usize) {
+        self.a.goto(i);
+        self.b.goto(i);
+        self.load_labels();
+    }
+    /// Goes to the next or previous image, the next or previous differing one if only those
+    /// are shown. Stays if there is none.
+    fn step(&mut self, forward: bool) {
+        let (_, current, len) = self.a.get_progress();
+        let mut candidates: Box<dyn Iterator<Item = usize>> = if forward {
+            Box::new(current + 1..len)
+        } else {
+            Box::new((0..current).rev())
+        };
+        let target = if self.only_differing {
+            candidates.find(|i| self.differs(*i))
+        } else {
+            candidates.next()
+        };
+        if let Some(i) = target {
+            self.seek(i);
+        }
+    }
+    fn draw_bbs(&self, ui: &mut Ui) {
+        let painter = ui.painter();
+        let size = self.image_rect.size();
+        let offset = self.image_rect.left_top().to_vec2();
+        let emphasized_a: Vec<usize> = self
+            .diff
+            .removed
+            .iter()
+            .copied()
+            .chain(self.diff.reclassified.iter().map(|m| m.a))
+            .chain(self.diff.moved.iter().map(|m| m.a))
+            .collect();
+        let emphasized_b: Vec<usize> = self
+            .diff
+            .added
+            .iter()
+            .copied()
+            .chain(self.diff.reclassified.iter().map(|m| m.b))
+            .chain(self.diff.moved.iter().map(|m| m.b))
+            .collect();
+        let stroke = |class: L, emphasized: bool| {
+            let color = class.color();
+            if emphasized {
+                Stroke::new(4.0, color)
+            } else {
+                let color = Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), 120);
+                Stroke::new(2.0, color)
+            }
+        };
+        let classes = L::all().len();
+        for (i, bb) in self.a_label.iter().enumerate() {
+            if bb.class_id() >= classes {
+                continue;
+            }
+            let rect = bb.rect(size).translate(offset);
+            painter.rect_stroke(
+                rect,
+                Rounding::none(),
+                stroke(bb.class(), emphasized_a.contains(&i)),
+            );
+            self.draw_label_text(painter, rect.left_bottom(), Align2::LEFT_BOTTOM, bb.class());
+        }
+        for (i, bb) in self.b_label.iter().enumerate() {
+            if bb.class_id() >= classes {
+                continue;
+            }
+            let rect = bb.rect(size).translate(offset);
+            let corners = [
+                rect.left_top(),
+                rect.right_top(),
+                rect.right_bottom(),
+                rect.left_bottom(),
+                rect.left_top(),
+            ];
+            painter.extend(Shape::dashed_line(
+                &corners,
+                stroke(bb.class(), emphasized_b.contains(&i)),
+                10.0,
+                6.0,
+            ));
+            self.draw_label_text(painter, rect.left_top(), Align2::LEFT_TOP, bb.class());
+        }
+    }
+    fn draw_label_text(&self, painter: &Painter, pos: Pos2, align: Align2, class: L) {
+        let galley =
+            painter.layout_no_wrap(class.to_name(), FontId::monospace(20.0), Color32::BLACK);
+        let rect = align.anchor_rect(Rect::from_min_size(pos, galley.size()));
+        painter.rect_filled(rect, Rounding::none(), class.color());
+        painter.galley(rect.min, galley);
+    }
+}
+
+impl<L: Label> eframe::App for Compare<L> {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let mut goto = None;
+        egui::Window::new("Boundrs Compare").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Current image:");
+                ui.label(self.a.current_name());
+            });
+            ui.horizontal(|ui| {
+                ui.label("Progress");
+                let (_, current, max) = self.a.get_progress();
+                goto = goto.or(widgets::seek_bar(ui, current, max));
+            });
+            goto = goto.or(widgets::search_box(ui, &mut self.search_query, &self.a));
+            ui.checkbox(
+                &mut self.only_differing,
+                "Only step through differing images",
+            );
+            ui.label("Solid boxes are a, dashed boxes are b");
+            ui.separator();
+            if let Some(notice) = &self.notice {
+                ui.colored_label(Color32::RED, notice);
+            }
+            ui.label(self.diff.summary());
+            for detail in self.diff.details(&self.a_label, &self.b_label) {
+                ui.monospace(detail);
+            }
+        });
+        if let Some(i) = goto {
+            self.seek(i);
+            self.update_texture(ctx);
+        }
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(Color32::BLACK))
+            .show(ctx, |ui| {
+                let img_response = ui.add(egui::Image::new(
+                    &self.image_texture,
+                    self.image_texture.size_vec2(),
+                ));
+                self.image_rect = img_response.rect;
+                self.draw_bbs(ui);
+
+                // Typing in the search box should not trigger shortcuts
+                if ctx.wants_keyboard_input() {
+                    return;
+                }
+                let next = self.bindings.pressed(ctx, Action::Next);
+                let previous = self.bindings.pressed(ctx, Action::Previous);
+                if next != previous {
+                    self.step(next);
+                    self.update_texture(ctx);
+                }
+                if keymap::cheat_sheet_pressed(ctx) {
+                    self.show_cheat_sheet = !self.show_cheat_sheet;
+                }
+            });
+        widgets::cheat_sheet::<L>(
+            ctx,
+            &mut self.show_cheat_sheet,
+            &self.bindings,
+            &HashMap::new(),
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use eframe::egui::{Rect, Vec2};
+use image::imageops::FilterType;
+use image::{Rgb, RgbImage};
+use std::path::Path;
+
+use crate::dataset::{BoundingBox, Dataset, Label};
+
+/// Gray of the letterbox border, as used by Ultralytics.
+pub const LETTERBOX_GRAY: u8 = 114;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CropShape {
+    /// The box with its aspect ratio, the longer side scaled to `--size`
+    Keep,
+    /// A square around the box, with more of the image on its shorter side where there is
+    Square,
+    /// The box padded to a square with gray
+    Letterbox,
+}
+
+/// Parses the padding of the crops, a part of the box size that can not be negative.
+pub fn parse_padding(padding: &str) -> std::result::Result<f32, String> {
+    match padding.trim().parse::<f32>() {
+        Ok(padding) if padding.is_finite() && padding >= 0.0 => Ok(padding),
+        _ => Err(format!(
+            "invalid padding {padding:?}, expected a number of at least 0"
+        )),
+    }
+}
+
+/// Cuts `rect` out of the image, shifting it inside the image where it sticks out if it fits.
+fn cut(image: &RgbImage, rect: Rect) -> RgbImage {
+    let size = Vec2::new(image.width() as f32, image.height() as f32);
+    let shift = Vec2::new(
+        (-rect.left()).max(0.0) - (rect.right() - size.x).max(0.0),
+        (-rect.top()).max(0.0) - (rect.bottom() - size.y).max(0.0),
+    );
+    let rect = rect
+        .translate(shift)
+        .intersect(Rect::from_min_size(Default::default(), size));
+    let x = (rect.left().round() as u32).min(image.width() - 1);
+    let y = (rect.top().round() as u32).min(image.height() - 1);
+    let width = (rect.width().round() as u32).clamp(1, image.width() - x);
+    let height = (rect.height().round() as u32).clamp(1, image.height() - y);
+    image::imageops::crop_imm(image, x, y, width, height).to_image()
+}
+
+/// Scales the crop so that its longer side is `size`, padded to a square unless `shape` keeps it.
+fn resize(crop: RgbImage, size: Option<u32>, shape: CropShape) -> RgbImage {
+    let (width, height) = (crop.width(), crop.height());
+    let side = size.unwrap_or(width.max(height));
+    let scale = side as f32 / width.max(height) as f32;
+    let scaled = (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    );
+    let crop = if scaled == (width, height) {
+        crop
+    } else {
+        image::imageops::resize(&crop, scaled.0, scaled.1, FilterType::Triangle)
+    };
+    match shape {
+        CropShape::Keep => crop,
+        CropShape::Square | CropShape::Letterbox => {
+            let mut square = RgbImage::from_pixel(side, side, Rgb([LETTERBOX_GRAY; 3]));
+            let x = (side - crop.width()) / 2;
+            let y = (side - crop.height()) / 2;
+            image::imageops::replace(&mut square, &crop, x as i64, y as i64);
+            square
+        }
+    }
+}
+
+/// Writes every box of the dataset as an image to `out/<class name>/<image>_<box>.jpg`,
+/// `<box>` being the index of the box in the label of the image.
+///
+/// The boxes are grown by `padding` times their size on each side. With `size` the longer
+/// side of each crop is scaled to it. Boxes of classes unknown to the label set are skipped.
+pub fn export_crops<L: Label>(
+    dataset: &Dataset<L>,
+    out: &Path,
+    padding: f32,
+    size: Option<u32>,
+    shape: CropShape,
+) -> Result<()> {
+    let mut crops = vec![0; L::all().len()];
+    let mut unknown = 0;
+    for i in 0..dataset.len() {
+        let label = dataset.label(i)?;
+        if label.is_empty() {
+            continue;
+        }
+        let path = dataset.image_path(i);
+        let image = image::open(path)
+            .with_context(|| format!("could not open {path:?}"))?
+            .to_rgb8();
+        let image_size = Vec2::new(image.width() as f32, image.height() as f32);
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        for (b, bb) in label.iter().enumerate() {
+            if bb.class_id() >= crops.len() {
+                unknown += 1;
+                continue;
+            }
+            let rect = bb.rect(image_size);
+            let mut rect = rect.expand2(rect.size() * padding);
+            if shape == CropShape::Square {
+                rect = Rect::from_center_size(
+                    rect.center(),
+                    Vec2::splat(rect.width().max(rect.height())),
+                );
+            }
+            let crop = resize(cut(&image, rect), size, shape);
+
+            let class = bb.class();
+            let dir = out.join(class.to_name());
+            std::fs::create_dir_all(&dir)?;
+            let crop_path = dir.join(format!("{stem}_{b}.jpg"));
+            crop.save(&crop_path)
+                .with_context(|| format!("could not write {crop_path:?}"))?;
+            crops[class.to_usize()] += 1;
+        }
+    }
+    for class in L::all() {
+        if crops[class.to_usize()] > 0 {
+            println!("{}: {} crops", class.to_name(), crops[class.to_usize()]);
+        }
+    }
+    println!("Wrote {} crops to {out:?}", crops.iter().sum::<usize>());
+    if unknown > 0 {
+        println!("Skipped {unknown} boxes of unknown classes");
+    }
+    Ok(())
+}
@@ -1,16 +1,22 @@
 use crate::egui::*;
-use anyhow::{Error, Result};
+use alphanumeric_sort::compare_path;
+use anyhow::{bail, Context, Error, Result};
 use glob::glob;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
 use std::marker::PhantomData;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use crate::order::Order;
+use crate::project::{Flag, ImageState, ProjectState, SavedOrder};
+use crate::proposals::Proposal;
+
 pub trait Label
 where
-    Self: std::fmt::Debug + Clone + Copy + PartialEq + Eq + std::hash::Hash,
+    Self: std::fmt::Debug + Clone + Copy + PartialEq + Eq + std::hash::Hash + 'static,
 {
     fn color(self) -> Color32;
     fn shortcuts() -> HashMap<Vec<Key>, Self>
@@ -19,6 +25,17 @@ where
     fn from_usize(i: usize) -> Self;
     fn to_usize(self) -> usize;
     fn to_name(self) -> String;
+    fn all() -> Vec<Self>
+    where
+        Self: Sized;
+    fn from_name(name: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        Self::all()
+            .into_iter()
+            .find(|class| class.to_name() == name)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -131,6 +148,10 @@ impl Label for Card {
             V2 => "2".into(),
         }
     }
+
+    fn all() -> Vec<Card> {
+        (0..13).map(Card::from_usize).collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -190,6 +211,9 @@ impl Label for Suit {
             Spades => "S".into(),
         }
     }
+    fn all() -> Vec<Suit> {
+        (0..4).map(Suit::from_usize).collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -231,17 +255,51 @@ impl Label for CardSuit {
         let (card, suit) = (self.0, self.1);
         format!("{}{}", card.to_name(), suit.to_name())
     }
+    fn all() -> Vec<CardSuit> {
+        (0..4 * 13).map(CardSuit::from_usize).collect()
+    }
+}
+
+/// Refines the classes of a coarse label into the finer classes of `T`, used when relabeling.
+pub trait Refine<T: Label>: Label {
+    /// Label whose shortcuts pick the refinement of a box, e.g. the suit of a card.
+    type Detail: Label;
+    /// The refined class, `None` if this class can not be refined with `detail`.
+    fn refine(self, detail: Self::Detail) -> Option<T>;
+    /// All classes this class can be refined into.
+    fn refinements(self) -> Vec<(Self::Detail, T)> {
+        Self::Detail::all()
+            .into_iter()
+            .filter_map(|detail| self.refine(detail).map(|class| (detail, class)))
+            .collect()
+    }
+}
+
+impl Refine<CardSuit> for Card {
+    type Detail = Suit;
+    fn refine(self, suit: Suit) -> Option<CardSuit> {
+        Some(CardSuit(self, suit))
+    }
+}
+
+impl Refine<CardSuit> for Suit {
+    type Detail = Card;
+    fn refine(self, card: Card) -> Option<CardSuit> {
+        Some(CardSuit(card, self))
+    }
 }
 
 pub type YoloLabel<L> = Vec<YoloBB<L>>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct YoloBB<L: Label> {
     class_num: usize,
     x: f32,
     y: f32,
     w: f32,
     h: f32,
+    // identity of the object across images, kept in a sidecar file of the label
+    track: Option<u32>,
     label: PhantomData<L>,
 }
 
@@ -250,6 +308,9 @@ impl<L: Label> FromStr for YoloBB<L> {
 
     fn from_str(s: &str) -> Result<Self> {
         let parts: Vec<_> = s.split(' ').collect();
+        if parts.len() < 5 {
+            bail!("expected 5 columns in {s:?}");
+        }
         let class_num: usize = parts[0].parse()?;
         let (x, y) = (parts[1].parse()?, parts[2].parse()?);
         let (w, h) = (parts[3].parse()?, parts[4].parse()?);
@@ -259,13 +320,41 @@ impl<L: Label> FromStr for YoloBB<L> {
             y,
             w,
             h,
-            label: PhantomData::default(),
+            track: None,
+            label: PhantomData,
         })
     }
 }
 
 impl<L: Label> YoloBB<L> {
-    fn as_string(self) -> String {
+    pub fn set_class(&mut self, class: L) {
+        self.class_num = class.to_usize();
+    }
+    /// Class id as stored in the label file, which might not be a class of `L`.
+    pub fn class_id(&self) -> usize {
+        self.class_num
+    }
+    pub fn set_class_id(&mut self, class_id: usize) {
+        self.class_num = class_id;
+    }
+    pub fn track(&self) -> Option<u32> {
+        self.track
+    }
+    pub fn set_track(&mut self, track: Option<u32>) {
+        self.track = track;
+    }
+    /// Box at `t` between `self` at 0 and `other` at 1, with the class and track of `self`.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        YoloBB {
+            x: lerp(self.x, other.x),
+            y: lerp(self.y, other.y),
+            w: lerp(self.w, other.w),
+            h: lerp(self.h, other.h),
+            ..*self
+        }
+    }
+    pub fn as_string(self) -> String {
         format!(
             "{} {} {} {} {}",
             self.class_num, self.x, self.y, self.w, self.h
@@ -281,8 +370,8 @@ pub trait BoundingBox<L: Label> {
 
 impl<L: Label> BoundingBox<L> for YoloBB<L> {
     fn rect(&self, size: Vec2) -> Rect {
-        let img_w = size.x as f32;
-        let img_h = size.y as f32;
+        let img_w = size.x;
+        let img_h = size.y;
         let yl = self;
         Rect::from_center_size(
             [yl.x * img_w, yl.y * img_h].into(),
@@ -293,14 +382,14 @@ impl<L: Label> BoundingBox<L> for YoloBB<L> {
         L::from_usize(self.class_num)
     }
     fn from_rect(rect: Rect, size: Vec2, class: L) -> Self {
-        let img_w = size.x as f32;
-        let img_h = size.y as f32;
+        let img_w = size.x;
+        let img_h = size.y;
         let center = rect.center();
-        let x = center.x as f32 / img_w;
-        let y = center.y as f32 / img_h;
+        let x = center.x / img_w;
+        let y = center.y / img_h;
         let size = rect.size();
-        let w = size.x as f32 / img_w;
-        let h = size.y as f32 / img_h;
+        let w = size.x / img_w;
+        let h = size.y / img_h;
         let class_num = class.to_usize();
         YoloBB {
             class_num,
@@ -308,11 +397,15 @@ impl<L: Label> BoundingBox<L> for YoloBB<L> {
             y,
             w,
             h,
-            label: PhantomData::default(),
+            track: None,
+            label: PhantomData,
         }
     }
 }
 
+/// Directory in the labels directory with the proposals of a model.
+pub const PROPOSALS_DIR: &str = "proposals";
+
 #[derive(Debug)]
 struct Datapoint<L: Label> {
     img_src: PathBuf,
@@ -337,7 +430,7 @@ impl<L: Label> Datapoint<L> {
         Datapoint::<L> {
             img_src,
             label_src,
-            label: PhantomData::default(),
+            label: PhantomData,
         }
     }
     fn load_image(&self) -> Result<ColorImage> {
@@ -347,6 +440,13 @@ impl<L: Label> Datapoint<L> {
         if !self.label_src.exists() {
             File::create(&self.label_src).unwrap();
         }
+        self.read_label()
+    }
+    /// Reads the label without creating the file, a missing file is an empty label.
+    fn read_label(&self) -> Result<YoloLabel<L>> {
+        if !self.label_src.exists() {
+            return Ok(vec![]);
+        }
         let yolo_strs = std::fs::read_to_string(&self.label_src)?;
 
         let mut labels = vec![];
@@ -354,13 +454,83 @@ impl<L: Label> Datapoint<L> {
             let label = YoloBB::from_str(line)?;
             labels.push(label)
         }
+        let tracks = self.read_tracks()?;
+        // track ids of a label that was changed by another tool don't belong to its boxes anymore
+        if tracks.len() == labels.len() {
+            for (bb, track) in labels.iter_mut().zip(tracks) {
+                bb.track = track;
+            }
+        }
         Ok(labels)
     }
+    /// Sidecar file with the track id of every box of the label, `-` for boxes without one.
+    fn tracks_src(&self) -> PathBuf {
+        self.label_src.with_extension("tracks")
+    }
+    fn read_tracks(&self) -> Result<Vec<Option<u32>>> {
+        let path = self.tracks_src();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        std::fs::read_to_string(&path)?
+            .lines()
+            .map(|line| match line.trim() {
+                "-" => Ok(None),
+                id => Ok(Some(id.parse()?)),
+            })
+            .collect()
+    }
+    /// Proposals of a model for the image, in the `proposals` directory next to the label.
+    fn proposals_src(&self) -> PathBuf {
+        let labels_dir = self.label_src.parent().unwrap();
+        labels_dir
+            .join(PROPOSALS_DIR)
+            .join(self.label_src.file_name().unwrap())
+    }
+    fn read_proposals(&self) -> Result<Vec<Proposal<L>>> {
+        let path = self.proposals_src();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        std::fs::read_to_string(&path)?
+            .lines()
+            .map(Proposal::from_str)
+            .collect::<Result<_>>()
+            .with_context(|| format!("in the proposals {path:?}"))
+    }
+    /// Writes the proposals, the file is removed when there are none left.
+    fn save_proposals(&self, proposals: &[Proposal<L>]) -> Result<()> {
+        let path = self.proposals_src();
+        if proposals.is_empty() {
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            return Ok(());
+        }
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = File::create(&path)?;
+        for proposal in proposals {
+            writeln!(file, "{}", proposal.as_string())?;
+        }
+        Ok(())
+    }
     fn save_label(&self, label: YoloLabel<L>) -> Result<()> {
         let mut file = File::create(&self.label_src)?;
-        for yolo_label in label {
+        for yolo_label in &label {
             writeln!(file, "{}", yolo_label.as_string())?;
         }
+        let tracks_src = self.tracks_src();
+        if label.iter().any(|bb| bb.track.is_some()) {
+            let mut file = File::create(&tracks_src)?;
+            for bb in &label {
+                match bb.track {
+                    Some(id) => writeln!(file, "{id}")?,
+                    None => writeln!(file, "-")?,
+                }
+            }
+        } else if tracks_src.exists() {
+            std::fs::remove_file(&tracks_src)?;
+        }
         println!("Saving labels to {:?}", self.label_src);
         Ok(())
     }
@@ -369,51 +539,152 @@ impl<L: Label> Datapoint<L> {
     }
 }
 
+/// Scores `candidate` if all characters of `query` appear in it in order, ignoring case.
+/// Consecutive matches and matches at the start of a word score higher.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous_match: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = candidate[next..].iter().position(|&c| c == q)? + next;
+        score += 1;
+        if previous_match.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        previous_match = Some(found);
+        next = found + 1;
+    }
+    // prefer shorter names when the matches are equally good
+    Some(score * 100 - candidate.len() as i32)
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum DatasetMovement<'c, L: Label> {
     Next,
     Previous,
     NextContaining(&'c HashSet<L>),
+    /// Next image that still has proposals to review
+    NextProposed,
     PreviousContaining(&'c HashSet<L>),
+    Goto(usize),
+}
+
+/// Directories with the images and the labels of a dataset.
+#[derive(Debug, Clone)]
+pub struct DatasetDirs {
+    pub images: PathBuf,
+    pub labels: PathBuf,
+}
+
+impl Default for DatasetDirs {
+    fn default() -> Self {
+        DatasetDirs {
+            images: PathBuf::from("./input"),
+            labels: PathBuf::from("./input"),
+        }
+    }
 }
 
 pub struct Dataset<L: Label> {
     data: Vec<Datapoint<L>>,
     i: usize,
+    state: ProjectState,
+    /// Order the images are sorted in, `None` for alphanumeric by name
+    order: Option<Order>,
 }
 
 impl<L: Label> Dataset<L> {
-    pub fn from_input_dir() -> Result<Self> {
+    pub fn open(dirs: &DatasetDirs) -> Result<Self> {
+        Dataset::from_dirs(&dirs.images, &dirs.labels)
+    }
+    /// Same images as in `dirs`, but with the labels in `labels_dir`, which is created if needed.
+    pub fn with_labels_dir(dirs: &DatasetDirs, labels_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(labels_dir)?;
+        Dataset::from_dirs(&dirs.images, labels_dir)
+    }
+    pub fn from_dirs(images_dir: &Path, labels_dir: &Path) -> Result<Self> {
         let mut data = vec![];
-        let labels_dir = PathBuf::from("./input");
-        let mut paths: Vec<_> = glob("./input/*.jpg")?.map(|p| p.unwrap()).collect();
+        let pattern = images_dir.join("*.jpg");
+        let mut paths: Vec<_> = glob(&pattern.to_string_lossy())?
+            .map(|p| p.unwrap())
+            .collect();
+        if paths.is_empty() {
+            bail!("no images found in {images_dir:?}");
+        }
         alphanumeric_sort::sort_path_slice(&mut paths);
         for img_src in paths.into_iter() {
-            data.push(Datapoint::new(img_src, labels_dir.clone()))
+            data.push(Datapoint::new(img_src, labels_dir.to_path_buf()))
         }
+        let mut dataset = Dataset {
+            data,
+            i: 0,
+            state: ProjectState::load(labels_dir)?,
+            order: None,
+        };
+        dataset.start_at_first_unlabeled();
+        Ok(dataset)
+    }
+    fn start_at_first_unlabeled(&mut self) {
         // start at first imgage without labels
-        let first_no_label = data
+        let first_no_label = self
+            .data
             .iter()
             .position(|p| !p.label_src.is_file())
             .unwrap_or(0);
         println!(
             "Starting at index {first_no_label} with label {:?}",
-            data[first_no_label]
+            self.data[first_no_label]
         );
+        self.i = first_no_label;
+    }
 
-        Ok(Dataset {
-            data,
-            i: first_no_label,
-        })
+    /// Sorts the images by `order` and saves the order in the project state.
+    pub fn set_order(&mut self, order: Order) -> Result<()> {
+        let indices = order.sort(self)?;
+        let images = indices.iter().map(|i| self.data[*i].name()).collect();
+        self.state.set_order(SavedOrder { order, images })?;
+        self.restore_order();
+        Ok(())
     }
-    pub fn with_label_prefix(prefix: &str) -> Result<Self> {
-        let mut dataset = Dataset::from_input_dir()?;
-        for mut datapoint in &mut dataset.data {
-            let label_name = datapoint.label_src.file_name().unwrap().to_str().unwrap();
-            let label_prefix_name = format!("{}{}", prefix, label_name);
-            datapoint.label_src = datapoint.label_src.with_file_name(label_prefix_name);
+    /// Sorts the images in the order saved in the project state, new images come last.
+    pub fn restore_order(&mut self) {
+        let Some(saved) = self.state.order() else {
+            return;
+        };
+        let positions: HashMap<&str, usize> = saved
+            .images
+            .iter()
+            .enumerate()
+            .map(|(position, name)| (name.as_str(), position))
+            .collect();
+        self.data.sort_by_key(|p| {
+            positions
+                .get(p.name().as_str())
+                .copied()
+                .unwrap_or(usize::MAX)
+        });
+        self.order = Some(saved.order.clone());
+        self.start_at_first_unlabeled();
+    }
+    /// Order the images are sorted in, `None` if they are in name order.
+    pub fn order(&self) -> Option<&Order> {
+        self.order.as_ref().filter(|order| **order != Order::Name)
+    }
+    /// Index of the image before the current one by name, which is the previous frame of a
+    /// video whatever the order. The first image is its own previous one.
+    fn previous_by_name(&self) -> usize {
+        if self.order().is_none() {
+            return self.i.saturating_sub(1);
         }
-        Ok(dataset)
+        let current = &self.data[self.i].img_src;
+        (0..self.data.len())
+            .filter(|j| compare_path(&self.data[*j].img_src, current) == Ordering::Less)
+            .max_by(|a, b| compare_path(&self.data[*a].img_src, &self.data[*b].img_src))
+            .unwrap_or(self.i)
     }
 
     pub fn current_image(&self) -> Result<ColorImage> {
@@ -423,15 +694,115 @@ impl<L: Label> Dataset<L> {
         self.data[self.i].load_label()
     }
     pub fn previous_label(&self) -> Result<YoloLabel<L>> {
-        let previous = self.i.saturating_sub(1);
-        self.data[previous].load_label()
+        self.data[self.previous_by_name()].load_label()
+    }
+    pub fn previous_image(&self) -> Result<ColorImage> {
+        self.data[self.previous_by_name()].load_image()
     }
     pub fn current_name(&self) -> String {
         self.data[self.i].name()
     }
+    pub fn current_state(&self) -> ImageState {
+        self.state.image(&self.current_name())
+    }
+    pub fn update_current_state(&mut self, update: impl FnOnce(&mut ImageState)) -> Result<()> {
+        let name = self.current_name();
+        self.state.update_image(&name, update)
+    }
+    pub fn image_path(&self, i: usize) -> &Path {
+        &self.data[i].img_src
+    }
+    /// Proposed boxes of the `i`-th image that were not reviewed yet.
+    pub fn proposals(&self, i: usize) -> Result<Vec<Proposal<L>>> {
+        self.data[i].read_proposals()
+    }
+    pub fn save_proposals(&self, i: usize, proposals: &[Proposal<L>]) -> Result<()> {
+        self.data[i].save_proposals(proposals)
+    }
+    /// Size of the `i`-th image, only reading the header of the file.
+    pub fn image_size(&self, i: usize) -> Result<Vec2> {
+        let (width, height) = image::image_dimensions(&self.data[i].img_src)?;
+        Ok(Vec2::new(width as f32, height as f32))
+    }
+    pub fn state(&self, i: usize) -> ImageState {
+        self.state.image(&self.data[i].name())
+    }
+    /// Writes generated labels of many images at once and flags them as interpolated for review.
+    pub fn save_interpolated(&mut self, labels: Vec<(usize, YoloLabel<L>)>) -> Result<()> {
+        let mut names = vec![];
+        for (i, label) in labels {
+            self.data[i].save_label(label)?;
+            names.push(self.data[i].name());
+        }
+        self.state.update_images(&names, |state| {
+            state.flags.insert(Flag::Interpolated);
+        })
+    }
+    /// Applies the same change to the state of many images, saving the project once.
+    pub fn update_states(
+        &mut self,
+        images: &[usize],
+        update: impl FnMut(&mut ImageState),
+    ) -> Result<()> {
+        let names: Vec<_> = images.iter().map(|i| self.data[*i].name()).collect();
+        self.state.update_images(&names, update)
+    }
+    /// Replaces the states of many images, saving the project once.
+    pub fn set_states(&mut self, states: Vec<(usize, ImageState)>) -> Result<()> {
+        let names = states
+            .into_iter()
+            .map(|(i, state)| (self.data[i].name(), state))
+            .collect();
+        self.state.set_images(names)
+    }
+    /// File of the project state, in the labels directory.
+    pub fn project_path(&self) -> &Path {
+        self.state.path()
+    }
+    /// Largest track id of all labels, if any box has one.
+    pub fn max_track(&self) -> Result<Option<u32>> {
+        let mut max = None;
+        for datapoint in &self.data {
+            max = max.max(datapoint.read_tracks()?.into_iter().flatten().max());
+        }
+        Ok(max)
+    }
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub fn label_path(&self, i: usize) -> &Path {
+        &self.data[i].label_src
+    }
+    pub fn tracks_path(&self, i: usize) -> PathBuf {
+        self.data[i].tracks_src()
+    }
+    pub fn has_label(&self, i: usize) -> bool {
+        self.data[i].label_src.is_file()
+    }
+    /// Label of the `i`-th image, empty if it has no label file.
+    pub fn label(&self, i: usize) -> Result<YoloLabel<L>> {
+        self.data[i].read_label()
+    }
+    pub fn save_label_at(&self, i: usize, label: YoloLabel<L>) -> Result<()> {
+        self.data[i].save_label(label)
+    }
     pub fn get_progress(&self) -> (usize, usize, usize) {
         (0, self.i, self.data.len())
     }
+    /// Indices and names of the images whose filename fuzzy matches `query`, best match first.
+    pub fn search(&self, query: &str) -> Vec<(usize, String)> {
+        let mut matches: Vec<_> = self
+            .data
+            .iter()
+            .enumerate()
+            .filter_map(|(i, datapoint)| {
+                let name = datapoint.name();
+                fuzzy_score(query, &name).map(|score| (score, i, name))
+            })
+            .collect();
+        matches.sort_by_key(|(score, i, _)| (std::cmp::Reverse(*score), *i));
+        matches.into_iter().map(|(_, i, name)| (i, name)).collect()
+    }
     fn save_label(&self, label: YoloLabel<L>) -> Result<()> {
         self.data[self.i].save_label(label)
     }
@@ -443,6 +814,10 @@ impl<L: Label> Dataset<L> {
         self.i = self.i.saturating_sub(1);
         Ok(())
     }
+    /// Moves to the `i`-th image without saving the label of the current one.
+    pub fn goto(&mut self, i: usize) {
+        self.i = std::cmp::min(i, self.data.len() - 1);
+    }
     fn next_containing(&mut self, classes: &HashSet<L>) -> Result<()> {
         while self.i < self.data.len() - 1 {
             self.i += 1;
@@ -453,6 +828,14 @@ impl<L: Label> Dataset<L> {
         }
         Ok(())
     }
+    fn next_proposed(&mut self) -> Result<()> {
+        if let Some(i) =
+            (self.i + 1..self.data.len()).find(|i| self.data[*i].proposals_src().is_file())
+        {
+            self.i = i;
+        }
+        Ok(())
+    }
     fn previous_containing(&mut self, classes: &HashSet<L>) -> Result<()> {
         while self.i > 0 {
             self.i -= 1;
@@ -470,7 +853,12 @@ impl<L: Label> Dataset<L> {
             DatasetMovement::Next => self.next(),
             DatasetMovement::Previous => self.previous(),
             DatasetMovement::NextContaining(classes) => self.next_containing(classes),
+            DatasetMovement::NextProposed => self.next_proposed(),
             DatasetMovement::PreviousContaining(classes) => self.previous_containing(classes),
+            DatasetMovement::Goto(i) => {
+                self.goto(i);
+                Ok(())
+            }
         }
     }
 }
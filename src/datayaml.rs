@@ -0,0 +1,237 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::dataset::{DatasetDirs, Label};
+
+/// The `data.yaml` of an Ultralytics dataset: where the splits are and the names of the classes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataYaml {
+    /// Root the split paths are relative to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub train: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub val: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test: Option<PathBuf>,
+    /// Number of classes, only kept up to date if the file has it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nc: Option<usize>,
+    /// Class names by class id
+    #[serde(deserialize_with = "list_or_map")]
+    pub names: BTreeMap<usize, String>,
+    /// Keys of other tools, written back as they were
+    #[serde(flatten)]
+    other: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Class names are either a list or a map from class id to name.
+fn list_or_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<usize, String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Names {
+        List(Vec<String>),
+        Map(BTreeMap<usize, String>),
+    }
+    Ok(match Names::deserialize(deserializer)? {
+        Names::List(names) => names.into_iter().enumerate().collect(),
+        Names::Map(names) => names,
+    })
+}
+
+/// Class names of the label set by class id.
+fn names_of<L: Label>() -> BTreeMap<usize, String> {
+    L::all()
+        .into_iter()
+        .map(|class| (class.to_usize(), class.to_name()))
+        .collect()
+}
+
+impl DataYaml {
+    /// A `data.yaml` with the classes of `L` and no splits.
+    pub fn for_label_set<L: Label>() -> Self {
+        DataYaml {
+            path: None,
+            train: None,
+            val: None,
+            test: None,
+            nc: None,
+            names: names_of::<L>(),
+            other: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("could not read {path:?}"))?;
+        serde_yaml::from_str(&content).with_context(|| format!("invalid {path:?}"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(self)?;
+        std::fs::write(path, content).with_context(|| format!("could not write {path:?}"))?;
+        Ok(())
+    }
+
+    /// Writes the class names and class count into the file at `path`, leaving the other lines
+    /// as they are. Only if the names can not be found in the text the whole file is rewritten.
+    pub fn save_names(&self, path: &Path) -> Result<()> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("could not read {path:?}"))?;
+        match replace_names(&content, &self.names)? {
+            Some(content) => {
+                std::fs::write(path, content).with_context(|| format!("could not write {path:?}"))
+            }
+            None => self.save(path),
+        }
+    }
+
+    /// Whether the class names are exactly the ones of `L`.
+    pub fn matches<L: Label>(&self) -> bool {
+        self.names == names_of::<L>()
+    }
+
+    /// Replaces the class names by the ones of `L`, keeping everything else.
+    pub fn set_label_set<L: Label>(&mut self) {
+        self.names = names_of::<L>();
+        if self.nc.is_some() {
+            self.nc = Some(self.names.len());
+        }
+    }
+
+    /// Image and label directories of a split, `path` being where this file is.
+    ///
+    /// Like Ultralytics, the labels are looked for in the `labels` directory that replaces the
+    /// last `images` directory of the image path, or next to the images if there is none.
+    /// The labels directory is created if it does not exist yet.
+    pub fn split_dirs(&self, path: &Path, split: &str) -> Result<DatasetDirs> {
+        let images = match split {
+            "train" => &self.train,
+            "val" => &self.val,
+            "test" => &self.test,
+            _ => bail!("unknown split {split:?}, expected train, val or test"),
+        };
+        let Some(images) = images else {
+            bail!("{path:?} has no {split} split");
+        };
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let root = match &self.path {
+            Some(root) => dir.join(root),
+            None => dir.to_path_buf(),
+        };
+        let images = root.join(images);
+        if !images.is_dir() {
+            bail!("the {split} split {images:?} is not a directory, image lists are not supported");
+        }
+        let components: Vec<_> = images.components().collect();
+        let labels = match components.iter().rposition(|c| c.as_os_str() == "images") {
+            Some(i) => {
+                let mut labels = components.clone();
+                labels[i] = Component::Normal("labels".as_ref());
+                labels.iter().collect()
+            }
+            None => images.clone(),
+        };
+        // a fresh dataset may have no labels yet
+        std::fs::create_dir_all(&labels)
+            .with_context(|| format!("could not create the labels directory {labels:?}"))?;
+        Ok(DatasetDirs { images, labels })
+    }
+}
+
+/// Replaces the top level `names` entry of the YAML `content`, with the lines belonging to it,
+/// and the value of `nc`. `None` if there is no `names` line to replace.
+fn replace_names(content: &str, names: &BTreeMap<usize, String>) -> Result<Option<String>> {
+    let lines: Vec<&str> = content.lines().collect();
+    let Some(start) = lines.iter().position(|line| line.starts_with("names:")) else {
+        return Ok(None);
+    };
+    // a block list or map, or a flow one spanning lines, is indented or starts with a dash
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| !line.starts_with([' ', '\t', '-']))
+        .map_or(lines.len(), |n| start + 1 + n);
+
+    let mut replaced = vec![];
+    for line in &lines[..start] {
+        replaced.push(replace_nc(line, names.len()));
+    }
+    replaced.push("names:".to_string());
+    for line in serde_yaml::to_string(names)?.lines() {
+        replaced.push(format!("  {line}"));
+    }
+    for line in &lines[end..] {
+        replaced.push(replace_nc(line, names.len()));
+    }
+    Ok(Some(replaced.join("\n") + "\n"))
+}
+
+/// The line with its value replaced by `nc` if it is the top level `nc` entry.
+fn replace_nc(line: &str, nc: usize) -> String {
+    if !line.starts_with("nc:") {
+        return line.to_string();
+    }
+    match line.find(" #") {
+        Some(comment) => format!("nc: {nc}{}", &line[comment..]),
+        None => format!("nc: {nc}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> BTreeMap<usize, String> {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .enumerate()
+            .collect()
+    }
+
+    #[test]
+    fn keeps_comments_and_order() {
+        let content = "# cards\npath: ../cards\nnames:\n  - a # first\n  - b\ntrain: images/train # split\nnc: 2 # classes\n";
+        let replaced = replace_names(content, &names(&["x", "y", "z"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            replaced,
+            "# cards\npath: ../cards\nnames:\n  0: x\n  1: y\n  2: z\ntrain: images/train # split\nnc: 3 # classes\n"
+        );
+        let data: DataYaml = serde_yaml::from_str(&replaced).unwrap();
+        assert_eq!(data.names, names(&["x", "y", "z"]));
+    }
+
+    #[test]
+    fn replaces_flow_and_unindented_lists() {
+        let flow = "names: [a,\n  b]\nval: images/val\n";
+        let replaced = replace_names(flow, &names(&["x"])).unwrap().unwrap();
+        assert_eq!(replaced, "names:\n  0: x\nval: images/val\n");
+
+        let block = "names:\n- a\n- b\n\nval: images/val";
+        let replaced = replace_names(block, &names(&["x"])).unwrap().unwrap();
+        assert_eq!(replaced, "names:\n  0: x\n\nval: images/val\n");
+    }
+
+    #[test]
+    fn quotes_names_yaml_would_misread() {
+        let replaced = replace_names("names: [a]\n", &names(&["yes", "a: b"]))
+            .unwrap()
+            .unwrap();
+        let data: DataYaml = serde_yaml::from_str(&replaced).unwrap();
+        assert_eq!(data.names, names(&["yes", "a: b"]));
+    }
+
+    #[test]
+    fn no_names_line() {
+        assert!(replace_names("\"names\": [a]\n", &names(&["x"]))
+            .unwrap()
+            .is_none());
+    }
+}
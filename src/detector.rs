@@ -0,0 +1,173 @@
+use anyhow::{anyhow, bail, Context, Result};
+use eframe::egui::{Rect, Vec2};
+use image::imageops::FilterType;
+use ort::session::Session;
+use ort::value::{Tensor, ValueType};
+use std::path::Path;
+
+use crate::crops::LETTERBOX_GRAY;
+use crate::dataset::{BoundingBox, Dataset, Label, YoloBB, PROPOSALS_DIR};
+use crate::matching::iou;
+use crate::proposals::{Origin, Proposal};
+
+/// Input size of models whose input shape is dynamic.
+const DEFAULT_INPUT_SIZE: usize = 640;
+
+/// A YOLOv8 style detector in the ONNX format, run on the CPU with ONNX Runtime.
+///
+/// The model takes a `1x3xSxS` RGB image scaled to 0..1 and outputs `1x(4+C)xN` boxes,
+/// center and size in input pixels followed by the score of each of the `C` classes.
+pub struct Detector {
+    session: Session,
+    input_size: usize,
+    confidence: f32,
+    nms_iou: f32,
+}
+
+impl Detector {
+    /// Loads the model, `runtime` is the ONNX Runtime library to use instead of the one found
+    /// through `ORT_DYLIB_PATH` or the library path.
+    pub fn load(
+        model: &Path,
+        runtime: Option<&Path>,
+        confidence: f32,
+        nms_iou: f32,
+    ) -> Result<Self> {
+        if !model.is_file() {
+            bail!("model {model:?} not found");
+        }
+        if let Some(runtime) = runtime {
+            if !runtime.is_file() {
+                bail!("ONNX Runtime library {runtime:?} not found");
+            }
+            ort::init_from(runtime.display().to_string()).commit()?;
+        }
+        let session = Session::builder()?
+            .commit_from_file(model)
+            .with_context(|| format!("could not load the model {model:?}"))?;
+        let input = session
+            .inputs
+            .first()
+            .ok_or_else(|| anyhow!("the model has no input"))?;
+        let input_size = match &input.input_type {
+            ValueType::Tensor { shape, .. } if shape.len() == 4 => match shape[3] {
+                size if size > 0 => size as usize,
+                _ => DEFAULT_INPUT_SIZE,
+            },
+            other => bail!("expected an image input of shape 1x3xSxS, found {other:?}"),
+        };
+        Ok(Detector {
+            session,
+            input_size,
+            confidence,
+            nms_iou,
+        })
+    }
+
+    /// Detects the boxes of classes of `L` in the image, the most confident first.
+    pub fn detect<L: Label>(&mut self, image_path: &Path) -> Result<Vec<Proposal<L>>> {
+        let image = image::open(image_path)?.to_rgb8();
+        let size = Vec2::new(image.width() as f32, image.height() as f32);
+        let s = self.input_size;
+
+        // letterbox: scale the longer side to the input size and pad the rest
+        let scale = s as f32 / size.x.max(size.y);
+        let (width, height) = ((size.x * scale) as u32, (size.y * scale) as u32);
+        let resized =
+            image::imageops::resize(&image, width.max(1), height.max(1), FilterType::Triangle);
+        let pad = Vec2::new(
+            (s as f32 - width as f32) / 2.0,
+            (s as f32 - height as f32) / 2.0,
+        );
+        let mut input = vec![LETTERBOX_GRAY as f32 / 255.0; 3 * s * s];
+        for (x, y, pixel) in resized.enumerate_pixels() {
+            let (x, y) = (x as usize + pad.x as usize, y as usize + pad.y as usize);
+            for c in 0..3 {
+                input[c * s * s + y * s + x] = pixel[c] as f32 / 255.0;
+            }
+        }
+
+        let input = Tensor::from_array(([1, 3, s, s], input))?;
+        let outputs = self.session.run(ort::inputs![input])?;
+        let (shape, values) = outputs[0].try_extract_tensor::<f32>()?;
+        let (rows, n) = match **shape {
+            [1, rows, n] if rows > 4 => (rows as usize, n as usize),
+            _ => bail!("expected an output of shape 1x(4+C)xN, found {shape:?}"),
+        };
+        let value = |row: usize, i: usize| values[row * n + i];
+
+        let mut detections = vec![];
+        for i in 0..n {
+            let (class, score) = (4..rows)
+                .map(|row| (row - 4, value(row, i)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            // classes the label set doesn't know can't become labels
+            if score < self.confidence || class >= L::all().len() {
+                continue;
+            }
+            let center = (Vec2::new(value(0, i), value(1, i)) - pad) / scale;
+            let box_size = Vec2::new(value(2, i), value(3, i)) / scale;
+            let rect = Rect::from_center_size(center.to_pos2(), box_size)
+                .intersect(Rect::from_min_size(Default::default(), size));
+            detections.push((rect, class, score));
+        }
+        detections.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        // non maximum suppression per class
+        let mut kept: Vec<(Rect, usize, f32)> = vec![];
+        for detection in detections {
+            let suppressed = kept
+                .iter()
+                .any(|k| k.1 == detection.1 && iou(k.0, detection.0) > self.nms_iou);
+            if !suppressed {
+                kept.push(detection);
+            }
+        }
+        Ok(kept
+            .into_iter()
+            .map(|(rect, class, confidence)| Proposal {
+                bb: YoloBB::from_rect(rect, size, L::from_usize(class)),
+                confidence,
+                origin: Origin::Predicted,
+            })
+            .collect())
+    }
+}
+
+/// Runs the detector over every image without a label and writes its proposals.
+pub fn prelabel<L: Label>(
+    dataset: &Dataset<L>,
+    detector: &mut Detector,
+    overwrite: bool,
+) -> Result<()> {
+    // an empty label is an image that was looked at and has no boxes
+    let unlabeled: Vec<usize> = (0..dataset.len())
+        .filter(|i| !dataset.has_label(*i))
+        .collect();
+    let mut existing = 0;
+    for i in &unlabeled {
+        if !dataset.proposals(*i)?.is_empty() {
+            existing += 1;
+        }
+    }
+    if existing > 0 && !overwrite {
+        bail!(
+            "{existing} of the unlabeled images already have proposals in {:?}, pass --overwrite to replace them",
+            dataset.label_path(0).parent().unwrap().join(PROPOSALS_DIR)
+        );
+    }
+    let (mut images, mut boxes) = (0, 0);
+    for i in unlabeled {
+        let image_path = dataset.image_path(i);
+        let proposals = detector
+            .detect::<L>(image_path)
+            .with_context(|| format!("while detecting in {image_path:?}"))?;
+        println!("{} boxes in {image_path:?}", proposals.len());
+        images += 1;
+        boxes += proposals.len();
+        dataset.save_proposals(i, &proposals)?;
+    }
+    println!("Proposed {boxes} boxes in {images} unlabeled images");
+    Ok(())
+}
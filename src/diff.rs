@@ -0,0 +1,252 @@
+use anyhow::Result;
+use eframe::egui::Rect;
+use std::collections::BTreeSet;
+
+use crate::dataset::{Dataset, Label, YoloBB};
+use crate::matching::{relative_rect, Match, Matcher};
+
+/// How the boxes of label `a` of an image became the boxes of label `b`.
+#[derive(Default, Debug, Clone)]
+pub struct LabelDiff {
+    /// Boxes with the same class at the same place
+    pub unchanged: Vec<Match>,
+    /// Boxes with the same class whose IoU is below the moved threshold
+    pub moved: Vec<Match>,
+    /// Boxes at the same place with another class
+    pub reclassified: Vec<Match>,
+    /// Boxes of `b` without a box in `a`
+    pub added: Vec<usize>,
+    /// Boxes of `a` without a box in `b`
+    pub removed: Vec<usize>,
+}
+
+impl LabelDiff {
+    pub fn is_empty(&self) -> bool {
+        self.moved.is_empty()
+            && self.reclassified.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+    }
+
+    /// Adds the counts of `other` to the counts of this diff, the box indices become meaningless.
+    fn add(&mut self, other: &LabelDiff) {
+        self.unchanged.extend(&other.unchanged);
+        self.moved.extend(&other.moved);
+        self.reclassified.extend(&other.reclassified);
+        self.added.extend(&other.added);
+        self.removed.extend(&other.removed);
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} removed, {} reclassified, {} moved, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.reclassified.len(),
+            self.moved.len(),
+            self.unchanged.len()
+        )
+    }
+
+    /// One line per change, with the class names and box indices.
+    pub fn details<L: Label>(&self, a: &[YoloBB<L>], b: &[YoloBB<L>]) -> Vec<String> {
+        let mut details = vec![];
+        for i in &self.added {
+            details.push(format!(
+                "+ {} (box {i} of b)",
+                class_name::<L>(b[*i].class_id())
+            ));
+        }
+        for i in &self.removed {
+            details.push(format!(
+                "- {} (box {i} of a)",
+                class_name::<L>(a[*i].class_id())
+            ));
+        }
+        for m in &self.reclassified {
+            details.push(format!(
+                "~ {} -> {} (boxes {} and {})",
+                class_name::<L>(a[m.a].class_id()),
+                class_name::<L>(b[m.b].class_id()),
+                m.a,
+                m.b
+            ));
+        }
+        for m in &self.moved {
+            details.push(format!(
+                "> {} moved, IoU {:.2} (boxes {} and {})",
+                class_name::<L>(a[m.a].class_id()),
+                m.iou,
+                m.a,
+                m.b
+            ));
+        }
+        details
+    }
+}
+
+/// Name of a class id, or the id if the label set doesn't know it.
+pub fn class_name<L: Label>(id: usize) -> String {
+    if id < L::all().len() {
+        L::from_usize(id).to_name()
+    } else {
+        format!("class {id}")
+    }
+}
+
+/// Matches the boxes of the same class first, the boxes left over are matched regardless of
+/// their class. Matches with an IoU below `moved_iou` are moves.
+pub fn diff_labels<L: Label>(
+    a: &[YoloBB<L>],
+    b: &[YoloBB<L>],
+    matcher: &Matcher,
+    moved_iou: f32,
+) -> LabelDiff {
+    let rects = |label: &[YoloBB<L>], indices: &[usize]| -> Vec<Rect> {
+        indices.iter().map(|i| relative_rect(&label[*i])).collect()
+    };
+    let matched = |matches: Vec<Match>, a_indices: &[usize], b_indices: &[usize]| -> Vec<Match> {
+        matches
+            .into_iter()
+            .map(|m| Match {
+                a: a_indices[m.a],
+                b: b_indices[m.b],
+                iou: m.iou,
+            })
+            .collect()
+    };
+
+    let mut diff = LabelDiff::default();
+    let classes: BTreeSet<usize> = a.iter().chain(b).map(|bb| bb.class_id()).collect();
+    let (mut a_matched, mut b_matched) = (vec![false; a.len()], vec![false; b.len()]);
+    for class in classes {
+        let a_indices: Vec<_> = (0..a.len()).filter(|i| a[*i].class_id() == class).collect();
+        let b_indices: Vec<_> = (0..b.len()).filter(|i| b[*i].class_id() == class).collect();
+        let matches = matcher.match_rects(&rects(a, &a_indices), &rects(b, &b_indices));
+        for m in matched(matches, &a_indices, &b_indices) {
+            (a_matched[m.a], b_matched[m.b]) = (true, true);
+            if m.iou >= moved_iou {
+                diff.unchanged.push(m);
+            } else {
+                diff.moved.push(m);
+            }
+        }
+    }
+
+    let a_left: Vec<_> = (0..a.len()).filter(|i| !a_matched[*i]).collect();
+    let b_left: Vec<_> = (0..b.len()).filter(|i| !b_matched[*i]).collect();
+    let matches = matcher.match_rects(&rects(a, &a_left), &rects(b, &b_left));
+    for m in matched(matches, &a_left, &b_left) {
+        (a_matched[m.a], b_matched[m.b]) = (true, true);
+        diff.reclassified.push(m);
+    }
+    diff.removed = (0..a.len()).filter(|i| !a_matched[*i]).collect();
+    diff.added = (0..b.len()).filter(|i| !b_matched[*i]).collect();
+    diff
+}
+
+/// Prints the differences of the labels of `a` and `b` per image and overall.
+pub fn diff<L: Label>(
+    a: &Dataset<L>,
+    b: &Dataset<L>,
+    matcher: &Matcher,
+    moved_iou: f32,
+) -> Result<()> {
+    let mut total = LabelDiff::default();
+    let (mut compared, mut differing) = (0, 0);
+    for i in 0..a.len() {
+        if !a.has_label(i) && !b.has_label(i) {
+            continue;
+        }
+        compared += 1;
+        let (a_label, b_label) = (a.label(i)?, b.label(i)?);
+        let diff = diff_labels(&a_label, &b_label, matcher, moved_iou);
+        total.add(&diff);
+        if diff.is_empty() {
+            continue;
+        }
+        differing += 1;
+        let name = a.image_path(i).file_name().unwrap().to_string_lossy();
+        let missing = match (a.has_label(i), b.has_label(i)) {
+            (false, _) => ", no label in a",
+            (_, false) => ", no label in b",
+            _ => "",
+        };
+        println!("{name}: {}{missing}", diff.summary());
+        for detail in diff.details(&a_label, &b_label) {
+            println!("  {detail}");
+        }
+    }
+    println!(
+        "{differing} of {compared} labeled images differ: {}",
+        total.summary()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Card;
+    use crate::matching::Assignment;
+
+    fn label(lines: &[&str]) -> Vec<YoloBB<Card>> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn pairs(matches: &[Match]) -> Vec<(usize, usize)> {
+        matches.iter().map(|m| (m.a, m.b)).collect()
+    }
+
+    fn diff(a: &[&str], b: &[&str]) -> LabelDiff {
+        let matcher = Matcher::new(0.5, Assignment::Hungarian);
+        diff_labels(&label(a), &label(b), &matcher, 0.9)
+    }
+
+    #[test]
+    fn changes_of_a_label() {
+        let diff = diff(
+            &[
+                "0 0.2 0.2 0.1 0.1",
+                "1 0.5 0.5 0.2 0.2",
+                "2 0.8 0.8 0.1 0.1",
+                "3 0.1 0.9 0.05 0.05",
+            ],
+            &[
+                "1 0.52 0.5 0.2 0.2",
+                "0 0.2 0.2 0.1 0.1",
+                "0 0.9 0.1 0.1 0.1",
+                "4 0.8 0.8 0.1 0.1",
+            ],
+        );
+        assert_eq!(pairs(&diff.unchanged), [(0, 1)]);
+        assert_eq!(pairs(&diff.moved), [(1, 0)]);
+        assert!((diff.moved[0].iou - 0.818).abs() < 0.001);
+        assert_eq!(pairs(&diff.reclassified), [(2, 3)]);
+        assert_eq!(diff.added, [2]);
+        assert_eq!(diff.removed, [3]);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn same_class_is_matched_before_reclassifying() {
+        // K overlaps the A of a more than the moved A of b does
+        let diff = diff(
+            &["0 0.5 0.5 0.2 0.2"],
+            &["1 0.5 0.5 0.2 0.2", "0 0.53 0.5 0.2 0.2"],
+        );
+        assert!(diff.unchanged.is_empty());
+        assert_eq!(pairs(&diff.moved), [(0, 1)]);
+        assert!(diff.reclassified.is_empty());
+        assert_eq!(diff.added, [0]);
+        assert!(diff.removed.is_empty());
+    }
+
+    #[test]
+    fn identical_labels_have_no_changes() {
+        let lines = ["0 0.2 0.2 0.1 0.1", "1 0.5 0.5 0.2 0.2"];
+        let diff = diff(&lines, &lines);
+        assert!(diff.is_empty());
+        assert_eq!(pairs(&diff.unchanged), [(0, 0), (1, 1)]);
+    }
+}
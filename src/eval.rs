@@ -0,0 +1,485 @@
+use anyhow::{bail, Context, Result};
+use eframe::egui::Rect;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::dataset::{Dataset, Label, YoloBB};
+use crate::diff::class_name;
+use crate::matching::{iou, relative_rect, Assignment, Matcher};
+use crate::predictions;
+use crate::proposals::Proposal;
+
+/// IoU of the confusion matrix and the false positives and negatives.
+const MATCH_IOU: f32 = 0.5;
+/// IoU thresholds of mAP@0.5:0.95.
+const COCO_IOUS: [f32; 10] = [0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95];
+/// Most confident predictions per image and class that count for the AP, COCO's `maxDets`.
+const MAX_DETECTIONS: usize = 100;
+
+#[derive(Serialize, Debug)]
+struct ClassAp {
+    class: String,
+    ground_truth: usize,
+    predictions: usize,
+    ap50: f32,
+    ap50_95: f32,
+}
+
+/// Rows are the predicted classes and columns the true classes, the last of both is background.
+#[derive(Serialize, Debug)]
+struct Confusion {
+    labels: Vec<String>,
+    matrix: Vec<Vec<usize>>,
+}
+
+#[derive(Serialize, Debug)]
+struct Miss {
+    image: String,
+    class: String,
+    /// Center and size relative to the image, like in the label files
+    #[serde(rename = "box")]
+    bb: [f32; 4],
+    /// Confidence of a false positive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confidence: Option<f32>,
+}
+
+#[derive(Serialize, Debug)]
+struct Evaluation {
+    images: usize,
+    map50: f32,
+    map50_95: f32,
+    classes: Vec<ClassAp>,
+    confusion: Confusion,
+    /// Most confident first
+    false_positives: Vec<Miss>,
+    /// Largest first
+    false_negatives: Vec<Miss>,
+}
+
+/// Ground truth and predictions of an image.
+struct Image<L: Label> {
+    name: String,
+    truth: Vec<YoloBB<L>>,
+    predictions: Vec<Proposal<L>>,
+}
+
+impl Miss {
+    fn new<L: Label>(image: &Image<L>, bb: &YoloBB<L>, confidence: Option<f32>) -> Self {
+        let rect = relative_rect(bb);
+        Miss {
+            image: image.name.clone(),
+            class: class_name::<L>(bb.class_id()),
+            bb: [
+                rect.center().x,
+                rect.center().y,
+                rect.width(),
+                rect.height(),
+            ],
+            confidence,
+        }
+    }
+}
+
+/// Area under the precision recall curve, interpolated at 101 recall points like COCO does.
+/// `hits` tells for each prediction, most confident first, whether it is a true positive.
+fn average_precision(hits: &[bool], ground_truth: usize) -> f32 {
+    let (mut precision, mut recall) = (vec![], vec![]);
+    let mut true_positives = 0;
+    for (k, hit) in hits.iter().enumerate() {
+        true_positives += *hit as usize;
+        precision.push(true_positives as f32 / (k + 1) as f32);
+        recall.push(true_positives as f32 / ground_truth as f32);
+    }
+    // the best precision at this or any higher recall
+    for k in (1..precision.len()).rev() {
+        precision[k - 1] = precision[k - 1].max(precision[k]);
+    }
+    let sum: f32 = (0..=100)
+        .map(|r| {
+            let k = recall.partition_point(|recall| *recall < r as f32 / 100.0);
+            precision.get(k).copied().unwrap_or(0.0)
+        })
+        .sum();
+    sum / 101.0
+}
+
+/// Average precision of the predictions of `class`, each matching the unmatched ground truth
+/// box of the class it overlaps most with by at least `threshold`, most confident first.
+/// Only the `MAX_DETECTIONS` most confident predictions of an image count.
+fn class_ap<L: Label>(images: &[Image<L>], class: usize, threshold: f32) -> f32 {
+    let mut predictions: Vec<(f32, usize, Rect)> = images
+        .iter()
+        .enumerate()
+        .flat_map(|(i, image)| {
+            let mut predictions: Vec<_> = image
+                .predictions
+                .iter()
+                .filter(|p| p.bb.class_id() == class)
+                .map(|p| (p.confidence, i, relative_rect(&p.bb)))
+                .collect();
+            predictions.sort_by(|a, b| b.0.total_cmp(&a.0));
+            predictions.truncate(MAX_DETECTIONS);
+            predictions
+        })
+        .collect();
+    predictions.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let truth: Vec<Vec<Rect>> = images
+        .iter()
+        .map(|image| {
+            image
+                .truth
+                .iter()
+                .filter(|bb| bb.class_id() == class)
+                .map(relative_rect)
+                .collect()
+        })
+        .collect();
+    let ground_truth: usize = truth.iter().map(Vec::len).sum();
+    let mut taken: Vec<Vec<bool>> = truth.iter().map(|rects| vec![false; rects.len()]).collect();
+    let hits: Vec<bool> = predictions
+        .iter()
+        .map(|(_, i, prediction)| {
+            let best = truth[*i]
+                .iter()
+                .enumerate()
+                .filter(|(t, _)| !taken[*i][*t])
+                .map(|(t, truth)| (t, iou(*prediction, *truth)))
+                .filter(|(_, iou)| *iou >= threshold)
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((t, _)) = best {
+                taken[*i][t] = true;
+            }
+            best.is_some()
+        })
+        .collect();
+    average_precision(&hits, ground_truth)
+}
+
+/// Writes a scores file with the worst images first, to open them in that order with
+/// `--order uncertainty`.
+fn write_scores(path: &Path, scores: &BTreeMap<&str, f32>) -> Result<PathBuf> {
+    let mut scores: Vec<_> = scores.iter().collect();
+    scores.sort_by(|a, b| b.1.total_cmp(a.1));
+    let mut csv = "image,score\n".to_string();
+    for (image, score) in scores {
+        writeln!(csv, "{image},{score}")?;
+    }
+    std::fs::write(path, csv).with_context(|| format!("could not write {path:?}"))?;
+    Ok(path.to_path_buf())
+}
+
+/// Evaluates the predictions in `source`, a directory with Yolo files with a confidence column
+/// or a COCO results file, against the labels of the dataset as ground truth.
+///
+/// Computes the COCO style AP at IoU 0.5 and averaged over 0.5 to 0.95 per class, and with the
+/// predictions of at least `confidence` the confusion matrix and the false positives and
+/// negatives at IoU 0.5. Prints a summary with the `worst` of them and writes `eval.json` and
+/// the images with false positives and negatives as scores files to `out`.
+pub fn eval<L: Label>(
+    dataset: &Dataset<L>,
+    source: &Path,
+    annotations: Option<&Path>,
+    confidence: f32,
+    worst: usize,
+    out: &Path,
+) -> Result<()> {
+    let mut predictions = predictions::read(dataset, source, annotations)?;
+    let classes = L::all().len();
+    let mut images = vec![];
+    for i in (0..dataset.len()).filter(|i| dataset.has_label(*i)) {
+        let path = dataset.image_path(i);
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let mut truth = dataset.label(i)?;
+        truth.retain(|bb| bb.class_id() < classes);
+        images.push(Image {
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            truth,
+            predictions: predictions.remove(stem.as_ref()).unwrap_or_default(),
+        });
+    }
+    if images.is_empty() {
+        bail!("no labeled images to evaluate against");
+    }
+    let unlabeled = predictions.values().filter(|p| !p.is_empty()).count();
+    if unlabeled > 0 {
+        println!("Ignored the predictions of {unlabeled} images without a label");
+    }
+
+    let mut aps = vec![];
+    for class in 0..classes {
+        let ground_truth: usize = images
+            .iter()
+            .map(|image| {
+                image
+                    .truth
+                    .iter()
+                    .filter(|bb| bb.class_id() == class)
+                    .count()
+            })
+            .sum();
+        let predicted: usize = images
+            .iter()
+            .map(|image| {
+                image
+                    .predictions
+                    .iter()
+                    .filter(|p| p.bb.class_id() == class)
+                    .count()
+            })
+            .sum();
+        // like COCO, classes without ground truth have no AP
+        if ground_truth == 0 {
+            if predicted > 0 {
+                println!(
+                    "{}: {predicted} predictions but no ground truth, left out of the mAP",
+                    class_name::<L>(class)
+                );
+            }
+            continue;
+        }
+        let per_iou: Vec<f32> = COCO_IOUS
+            .iter()
+            .map(|threshold| class_ap(&images, class, *threshold))
+            .collect();
+        aps.push(ClassAp {
+            class: class_name::<L>(class),
+            ground_truth,
+            predictions: predicted,
+            ap50: per_iou[0],
+            ap50_95: per_iou.iter().sum::<f32>() / per_iou.len() as f32,
+        });
+    }
+    let mean = |ap: fn(&ClassAp) -> f32| {
+        if aps.is_empty() {
+            0.0
+        } else {
+            aps.iter().map(ap).sum::<f32>() / aps.len() as f32
+        }
+    };
+    let (map50, map50_95) = (mean(|ap| ap.ap50), mean(|ap| ap.ap50_95));
+
+    let matcher = Matcher::new(MATCH_IOU, Assignment::Hungarian);
+    let background = classes;
+    let mut matrix = vec![vec![0; classes + 1]; classes + 1];
+    let (mut false_positives, mut false_negatives) = (vec![], vec![]);
+    for image in &images {
+        let shown: Vec<_> = image
+            .predictions
+            .iter()
+            .filter(|p| p.confidence >= confidence)
+            .collect();
+        let truth_rects: Vec<_> = image.truth.iter().map(relative_rect).collect();
+        let shown_rects: Vec<_> = shown.iter().map(|p| relative_rect(&p.bb)).collect();
+
+        // regardless of the classes for the confusion matrix
+        let (mut truth_matched, mut shown_matched) =
+            (vec![false; truth_rects.len()], vec![false; shown.len()]);
+        for m in matcher.match_rects(&shown_rects, &truth_rects) {
+            matrix[shown[m.a].bb.class_id()][image.truth[m.b].class_id()] += 1;
+            (shown_matched[m.a], truth_matched[m.b]) = (true, true);
+        }
+        for (p, _) in shown_matched.iter().enumerate().filter(|(_, m)| !**m) {
+            matrix[shown[p].bb.class_id()][background] += 1;
+        }
+        for (t, _) in truth_matched.iter().enumerate().filter(|(_, m)| !**m) {
+            matrix[background][image.truth[t].class_id()] += 1;
+        }
+
+        // only within a class for the false positives and negatives
+        let (mut truth_matched, mut shown_matched) =
+            (vec![false; truth_rects.len()], vec![false; shown.len()]);
+        for class in 0..classes {
+            let ts: Vec<_> = (0..truth_rects.len())
+                .filter(|t| image.truth[*t].class_id() == class)
+                .collect();
+            let ps: Vec<_> = (0..shown.len())
+                .filter(|p| shown[*p].bb.class_id() == class)
+                .collect();
+            if ts.is_empty() && ps.is_empty() {
+                continue;
+            }
+            let rects = |indices: &[usize], rects: &[Rect]| -> Vec<Rect> {
+                indices.iter().map(|i| rects[*i]).collect()
+            };
+            for m in matcher.match_rects(&rects(&ps, &shown_rects), &rects(&ts, &truth_rects)) {
+                (shown_matched[ps[m.a]], truth_matched[ts[m.b]]) = (true, true);
+            }
+        }
+        for (p, _) in shown_matched.iter().enumerate().filter(|(_, m)| !**m) {
+            false_positives.push(Miss::new(image, &shown[p].bb, Some(shown[p].confidence)));
+        }
+        for (t, _) in truth_matched.iter().enumerate().filter(|(_, m)| !**m) {
+            false_negatives.push(Miss::new(image, &image.truth[t], None));
+        }
+    }
+    false_positives.sort_by(|a, b| b.confidence.unwrap().total_cmp(&a.confidence.unwrap()));
+    false_negatives.sort_by(|a, b| (b.bb[2] * b.bb[3]).total_cmp(&(a.bb[2] * a.bb[3])));
+
+    let used: Vec<usize> = (0..=classes)
+        .filter(|c| {
+            matrix[*c].iter().sum::<usize>() + matrix.iter().map(|row| row[*c]).sum::<usize>() > 0
+        })
+        .collect();
+    let labels: Vec<String> = (0..=classes)
+        .map(|c| {
+            if c == background {
+                "background".to_string()
+            } else {
+                class_name::<L>(c)
+            }
+        })
+        .collect();
+
+    println!("{} labeled images", images.len());
+    println!("mAP@0.5 {map50:.3}, mAP@0.5:0.95 {map50_95:.3}");
+    println!(
+        "{:>12} {:>8} {:>11} {:>7} {:>12}",
+        "class", "boxes", "predictions", "AP@0.5", "AP@0.5:0.95"
+    );
+    for ap in &aps {
+        println!(
+            "{:>12} {:>8} {:>11} {:>7.3} {:>12.3}",
+            ap.class, ap.ground_truth, ap.predictions, ap.ap50, ap.ap50_95
+        );
+    }
+    println!("Confusion matrix at confidence {confidence}, predicted rows and true columns:");
+    let mut line = format!("{:>12}", "");
+    for c in &used {
+        write!(line, " {:>10}", labels[*c])?;
+    }
+    println!("{line}");
+    for row in &used {
+        let mut line = format!("{:>12}", labels[*row]);
+        for column in &used {
+            write!(line, " {:>10}", matrix[*row][*column])?;
+        }
+        println!("{line}");
+    }
+    println!("{} false positives, most confident:", false_positives.len());
+    for miss in false_positives.iter().take(worst) {
+        println!(
+            "  {} {} {:.2}",
+            miss.image,
+            miss.class,
+            miss.confidence.unwrap()
+        );
+    }
+    println!("{} false negatives, largest:", false_negatives.len());
+    for miss in false_negatives.iter().take(worst) {
+        println!("  {} {}", miss.image, miss.class);
+    }
+
+    std::fs::create_dir_all(out)?;
+    let mut fp_scores: BTreeMap<&str, f32> = BTreeMap::new();
+    for miss in &false_positives {
+        let score = fp_scores.entry(&miss.image).or_default();
+        *score = score.max(miss.confidence.unwrap());
+    }
+    let mut fn_scores: BTreeMap<&str, f32> = BTreeMap::new();
+    for miss in &false_negatives {
+        *fn_scores.entry(&miss.image).or_default() += 1.0;
+    }
+    let scores_files = [
+        write_scores(&out.join("false_positives.csv"), &fp_scores)?,
+        write_scores(&out.join("false_negatives.csv"), &fn_scores)?,
+    ];
+
+    let evaluation = Evaluation {
+        images: images.len(),
+        map50,
+        map50_95,
+        classes: aps,
+        confusion: Confusion { labels, matrix },
+        false_positives,
+        false_negatives,
+    };
+    let path = out.join("eval.json");
+    let file =
+        std::fs::File::create(&path).with_context(|| format!("could not create {path:?}"))?;
+    serde_json::to_writer_pretty(file, &evaluation)?;
+    println!("Wrote the evaluation to {path:?}");
+    for path in scores_files {
+        println!(
+            "Open the images worst first with: boundrs label --order uncertainty --scores {path:?}"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Card;
+    use crate::proposals::Origin;
+
+    fn bb(line: &str) -> YoloBB<Card> {
+        line.parse().unwrap()
+    }
+
+    fn prediction(line: &str, confidence: f32) -> Proposal<Card> {
+        Proposal {
+            bb: bb(line),
+            confidence,
+            origin: Origin::Predicted,
+        }
+    }
+
+    #[test]
+    fn average_precision_at_101_recall_points() {
+        // precision 1, 1, 2/3, 3/4 at recall 0.2, 0.4, 0.4, 0.6, interpolated to 1 up to
+        // recall 0.4 (41 points) and 3/4 up to 0.6 (20 points)
+        let ap = average_precision(&[true, true, false, true], 5);
+        assert!((ap - 56.0 / 101.0).abs() < 1e-6, "{ap}");
+        assert_eq!(average_precision(&[], 5), 0.0);
+        assert_eq!(average_precision(&[true], 1), 1.0);
+    }
+
+    fn images() -> Vec<Image<Card>> {
+        vec![
+            Image {
+                name: "a.jpg".into(),
+                truth: vec![bb("0 0.25 0.25 0.2 0.2"), bb("0 0.75 0.75 0.2 0.2")],
+                predictions: vec![
+                    prediction("0 0.25 0.25 0.2 0.2", 0.9),
+                    // a second prediction of the same box is a false positive
+                    prediction("0 0.25 0.25 0.2 0.2", 0.8),
+                    // IoU 0.74 with the second box
+                    prediction("0 0.75 0.78 0.2 0.2", 0.7),
+                ],
+            },
+            Image {
+                name: "b.jpg".into(),
+                truth: vec![bb("0 0.5 0.5 0.2 0.2")],
+                // another class does not count for this one
+                predictions: vec![prediction("1 0.5 0.5 0.2 0.2", 0.95)],
+            },
+        ]
+    }
+
+    #[test]
+    fn class_ap_matches_the_most_confident_first() {
+        // hits true, false, true of 3 boxes: precision 1 up to recall 1/3 (34 points),
+        // then 2/3 up to recall 2/3 (33 points)
+        let ap = class_ap(&images(), 0, 0.5);
+        assert!((ap - 56.0 / 101.0).abs() < 1e-6, "{ap}");
+        // the shifted box is no match anymore
+        let ap = class_ap(&images(), 0, 0.75);
+        assert!((ap - 34.0 / 101.0).abs() < 1e-6, "{ap}");
+        assert_eq!(class_ap(&images(), 2, 0.5), 0.0);
+    }
+
+    #[test]
+    fn class_ap_counts_the_most_confident_predictions_of_an_image() {
+        let mut predictions = vec![prediction("0 0.25 0.25 0.2 0.2", 0.1)];
+        predictions.extend((0..MAX_DETECTIONS).map(|_| prediction("0 0.75 0.75 0.1 0.1", 0.9)));
+        let images = [Image {
+            name: "a.jpg".into(),
+            truth: vec![bb("0 0.25 0.25 0.2 0.2")],
+            predictions,
+        }];
+        assert_eq!(class_ap(&images, 0, 0.5), 0.0);
+    }
+}
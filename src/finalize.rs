@@ -0,0 +1,199 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dataset::{Dataset, Label};
+use crate::project::{Flag, ImageState};
+
+/// A file of the dataset that is replaced by a refined one, or removed if there is none.
+struct Change {
+    dst: PathBuf,
+    src: Option<PathBuf>,
+}
+
+impl Change {
+    /// Where the refined file is copied to before it replaces `dst`.
+    fn staged(&self) -> PathBuf {
+        let mut name = self.dst.file_name().unwrap().to_os_string();
+        name.push(".tmp");
+        self.dst.with_file_name(name)
+    }
+}
+
+/// Copies `path` into `backup_dir` if it exists.
+fn back_up(path: &Path, backup_dir: &Path) -> Result<()> {
+    if path.is_file() {
+        let backup = backup_dir.join(path.file_name().unwrap());
+        fs::copy(path, &backup)
+            .with_context(|| format!("could not copy {path:?} to {backup:?}"))?;
+    }
+    Ok(())
+}
+
+/// Undoes the first `done` changes with the copies in `backup_dir` and removes the staged files.
+fn roll_back(changes: &[Change], done: usize, backup_dir: &Path) -> Result<()> {
+    for change in &changes[..done] {
+        let backup = backup_dir.join(change.dst.file_name().unwrap());
+        if backup.is_file() {
+            fs::copy(&backup, &change.dst)?;
+        } else if change.dst.is_file() {
+            fs::remove_file(&change.dst)?;
+        }
+    }
+    for change in changes {
+        let _ = fs::remove_file(change.staged());
+    }
+    Ok(())
+}
+
+/// Moves the labels of `refined` into `dataset`, keeping the replaced labels in `backup_dir`.
+///
+/// Every refined label is checked first, nothing is touched if one of them is broken, flagged as
+/// incomplete or empty although its source label has boxes that were not skipped. Without
+/// `merge` the labels of images that have no refined label are moved to the backup too, so that
+/// the dataset ends up with exactly the refined labels.
+///
+/// The track ids and the flags of the images go along with their labels, the boxes skipped while
+/// refining are forgotten. All refined files are staged before the first label is replaced, and
+/// if replacing fails the labels replaced so far are restored from the backup.
+pub fn finalize<L: Label>(
+    dataset: &mut Dataset<L>,
+    refined: &Dataset<L>,
+    merge: bool,
+    backup_dir: &Path,
+) -> Result<()> {
+    let refined_labels = (0..refined.len()).filter(|i| refined.has_label(*i)).count();
+    if refined_labels == 0 {
+        bail!("not finalizing, there are no refined labels");
+    }
+    let mut broken = vec![];
+    for i in (0..refined.len()).filter(|i| refined.has_label(*i)) {
+        let path = refined.label_path(i);
+        let state = refined.state(i);
+        if state.flags.contains(&Flag::Incomplete) {
+            broken.push(format!("{path:?}: {}", Flag::Incomplete.description()));
+            continue;
+        }
+        match refined.label(i) {
+            Err(e) => broken.push(format!("{path:?}: {e}")),
+            Ok(label) if label.iter().any(|bb| bb.class_id() >= L::all().len()) => {
+                broken.push(format!("{path:?}: unknown class"))
+            }
+            // visiting an image while relabeling creates an empty label
+            Ok(label) if label.is_empty() && dataset.label(i)?.len() > state.ignored.len() => {
+                broken.push(format!("{path:?}: empty, the source label has boxes"))
+            }
+            Ok(_) => {}
+        }
+    }
+    if !broken.is_empty() {
+        bail!(
+            "not finalizing, broken refined labels:\n  {}",
+            broken.join("\n  ")
+        );
+    }
+
+    // a second backup would overwrite the originals in the first one
+    if backup_dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        bail!("not finalizing, the backup directory {backup_dir:?} is not empty, choose another with --backup");
+    }
+
+    let mut changes = vec![];
+    let mut states = vec![];
+    let (mut replaced, mut removed, mut kept) = (0, 0, 0);
+    for i in 0..dataset.len() {
+        let tracks = dataset.tracks_path(i);
+        if refined.has_label(i) {
+            changes.push(Change {
+                dst: dataset.label_path(i).to_path_buf(),
+                src: Some(refined.label_path(i).to_path_buf()),
+            });
+            let refined_tracks = refined.tracks_path(i);
+            if refined_tracks.is_file() || tracks.is_file() {
+                changes.push(Change {
+                    dst: tracks,
+                    src: refined_tracks.is_file().then_some(refined_tracks),
+                });
+            }
+            // the skipped boxes are indices into the replaced label
+            let state = ImageState {
+                ignored: Default::default(),
+                flags: refined.state(i).flags,
+            };
+            states.push((i, state));
+            replaced += 1;
+        } else if dataset.has_label(i) && !merge {
+            changes.push(Change {
+                dst: dataset.label_path(i).to_path_buf(),
+                src: None,
+            });
+            if tracks.is_file() {
+                changes.push(Change {
+                    dst: tracks,
+                    src: None,
+                });
+            }
+            states.push((i, ImageState::default()));
+            removed += 1;
+        } else if dataset.has_label(i) {
+            kept += 1;
+        }
+    }
+
+    let stage = || -> Result<()> {
+        fs::create_dir_all(backup_dir)?;
+        for change in &changes {
+            back_up(&change.dst, backup_dir)?;
+            if let Some(src) = &change.src {
+                let staged = change.staged();
+                fs::copy(src, &staged)
+                    .with_context(|| format!("could not copy {src:?} to {staged:?}"))?;
+            }
+        }
+        back_up(dataset.project_path(), backup_dir)
+    };
+    if let Err(e) = stage() {
+        roll_back(&changes, 0, backup_dir)?;
+        let _ = fs::remove_dir_all(backup_dir);
+        return Err(e.context("not finalizing, the refined labels could not be staged"));
+    }
+
+    let mut done = 0;
+    let commit = || -> Result<()> {
+        for change in &changes {
+            // counted first, a failed rename may have happened anyway
+            done += 1;
+            match &change.src {
+                Some(_) => fs::rename(change.staged(), &change.dst),
+                None => fs::remove_file(&change.dst),
+            }
+            .with_context(|| format!("could not replace {:?}", change.dst))?;
+        }
+        dataset.set_states(states)
+    };
+    if let Err(e) = commit() {
+        roll_back(&changes, done, backup_dir)?;
+        let project = dataset.project_path();
+        let backup = backup_dir.join(project.file_name().unwrap());
+        if backup.is_file() {
+            fs::copy(&backup, project)?;
+        } else if project.is_file() {
+            fs::remove_file(project)?;
+        }
+        return Err(e.context(format!(
+            "finalizing failed, the previous labels were restored from {backup_dir:?}"
+        )));
+    }
+
+    println!("Replaced {replaced} labels with the refined ones");
+    if merge {
+        println!("Kept {kept} labels of images that were not relabeled");
+    } else {
+        println!("Removed {removed} labels of images that were not relabeled");
+    }
+    println!("The previous labels are in {backup_dir:?}");
+    Ok(())
+}
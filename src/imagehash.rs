@@ -0,0 +1,23 @@
+use anyhow::Result;
+use image::imageops::FilterType;
+use std::path::Path;
+
+/// Difference hash of an image: each bit tells whether a pixel of the 9x8 grayscale thumbnail
+/// is brighter than its right neighbour. Similar images have hashes with a small Hamming distance.
+pub fn dhash(path: &Path) -> Result<u64> {
+    let image = image::open(path)?.to_luma8();
+    let thumbnail = image::imageops::resize(&image, 9, 8, FilterType::Triangle);
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    Ok(hash)
+}
+
+/// Number of differing bits of two hashes, 0 for identical thumbnails and at most 64.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
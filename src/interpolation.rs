@@ -0,0 +1,90 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+use crate::dataset::{Dataset, Label, YoloLabel};
+use crate::project::Flag;
+
+/// A keyframe is an image with a label that was drawn rather than interpolated. An empty label
+/// is a keyframe too, the tracked objects left the frame.
+pub fn is_keyframe<L: Label>(dataset: &Dataset<L>, i: usize) -> bool {
+    dataset.has_label(i) && !dataset.state(i).flags.contains(&Flag::Interpolated)
+}
+
+/// Closest keyframe before the `i`-th image.
+pub fn previous_keyframe<L: Label>(dataset: &Dataset<L>, i: usize) -> Option<usize> {
+    (0..i).rev().find(|j| is_keyframe(dataset, *j))
+}
+
+/// Labels of the images between the keyframes `from` and `to`, with the boxes that have the same
+/// track id in both keyframes moved linearly. Boxes without a partner are left out.
+fn interpolate_labels<L: Label>(
+    from_label: &YoloLabel<L>,
+    to_label: &YoloLabel<L>,
+    from: usize,
+    to: usize,
+) -> Vec<(usize, YoloLabel<L>)> {
+    let to_tracks: HashMap<u32, _> = to_label
+        .iter()
+        .filter_map(|bb| bb.track().map(|track| (track, bb)))
+        .collect();
+    let pairs: Vec<_> = from_label
+        .iter()
+        .filter_map(|bb| Some((bb, *to_tracks.get(&bb.track()?)?)))
+        .collect();
+    (from + 1..to)
+        .map(|i| {
+            let t = (i - from) as f32 / (to - from) as f32;
+            let label = pairs.iter().map(|(a, b)| a.lerp(b, t)).collect();
+            (i, label)
+        })
+        .collect()
+}
+
+/// Interpolates the images between the keyframes `from` and `to` and returns the number of
+/// boxes per image.
+pub fn interpolate<L: Label>(dataset: &mut Dataset<L>, from: usize, to: usize) -> Result<usize> {
+    if to <= from + 1 {
+        return Ok(0);
+    }
+    for i in from + 1..to {
+        if is_keyframe(dataset, i) {
+            bail!(
+                "{:?} between the keyframes is a keyframe itself",
+                dataset.label_path(i)
+            );
+        }
+    }
+    let labels = interpolate_labels(&dataset.label(from)?, &dataset.label(to)?, from, to);
+    let boxes = labels.first().map_or(0, |(_, label)| label.len());
+    dataset.save_interpolated(labels)?;
+    Ok(boxes)
+}
+
+/// Interpolates between every pair of consecutive keyframes of the dataset.
+pub fn interpolate_all<L: Label>(dataset: &mut Dataset<L>) -> Result<()> {
+    let mut keyframes = vec![];
+    for i in 0..dataset.len() {
+        if is_keyframe(dataset, i) {
+            keyframes.push(i);
+        }
+    }
+    let mut images = 0;
+    for pair in keyframes.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let boxes = interpolate(dataset, from, to)?;
+        if to > from + 1 {
+            println!(
+                "Interpolated {boxes} boxes into {} images between {:?} and {:?}",
+                to - from - 1,
+                dataset.label_path(from),
+                dataset.label_path(to),
+            );
+            images += to - from - 1;
+        }
+    }
+    println!(
+        "Interpolated {images} images between {} keyframes",
+        keyframes.len()
+    );
+    Ok(())
+}
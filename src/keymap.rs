@@ -0,0 +1,452 @@
+use anyhow::{anyhow, bail, Context as _, Result};
+use eframe::egui;
+use egui::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use crate::dataset::Label;
+use crate::widgets::shortcut_text;
+
+/// Keymap that is picked up from the working directory if no other file is given.
+pub const DEFAULT_KEYMAP_FILE: &str = "keymap.toml";
+
+#[rustfmt::skip]
+const KEYS: [Key; 71] = [
+    Key::ArrowDown, Key::ArrowLeft, Key::ArrowRight, Key::ArrowUp,
+    Key::Escape, Key::Tab, Key::Backspace, Key::Enter, Key::Space,
+    Key::Insert, Key::Delete, Key::Home, Key::End, Key::PageUp, Key::PageDown,
+    Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+    Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10,
+    Key::F11, Key::F12, Key::F13, Key::F14, Key::F15, Key::F16, Key::F17, Key::F18, Key::F19, Key::F20,
+];
+
+/// Parses a key by its egui name, e.g. `ArrowRight` or `D`. Digits may be given without `Num`.
+fn parse_key(name: &str) -> Result<Key> {
+    KEYS.iter()
+        .find(|key| {
+            let key_name = format!("{key:?}");
+            key_name.eq_ignore_ascii_case(name) || key_name.strip_prefix("Num") == Some(name)
+        })
+        .copied()
+        .ok_or_else(|| anyhow!("unknown key {name:?}"))
+}
+
+/// Parses a space separated key sequence like `H 1`.
+fn parse_sequence(sequence: &str) -> Result<Vec<Key>> {
+    let keys: Vec<Key> = sequence
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Result<_>>()?;
+    if keys.is_empty() {
+        bail!("empty key sequence");
+    }
+    Ok(keys)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    Next,
+    Previous,
+    Filter,
+    Repeat,
+    Clear,
+    Skip,
+    Advance,
+    Propagate,
+    Accept,
+    Reject,
+    AcceptAll,
+    Interpolate,
+    PropagateTracks,
+    EditProposal,
+    NextUnreviewed,
+}
+
+impl Action {
+    const ALL: [Action; 15] = [
+        Action::Next,
+        Action::Previous,
+        Action::Filter,
+        Action::Repeat,
+        Action::Clear,
+        Action::Skip,
+        Action::Advance,
+        Action::Propagate,
+        Action::Accept,
+        Action::Reject,
+        Action::AcceptAll,
+        Action::Interpolate,
+        Action::PropagateTracks,
+        Action::EditProposal,
+        Action::NextUnreviewed,
+    ];
+
+    /// Name of the action in the keymap file.
+    fn name(self) -> &'static str {
+        use Action::*;
+        match self {
+            Next => "next",
+            Previous => "previous",
+            Filter => "filter",
+            Repeat => "repeat",
+            Clear => "clear",
+            Skip => "skip",
+            Advance => "advance",
+            Propagate => "propagate",
+            Accept => "accept",
+            Reject => "reject",
+            AcceptAll => "accept_all",
+            Interpolate => "interpolate",
+            PropagateTracks => "propagate_tracks",
+            EditProposal => "edit_proposal",
+            NextUnreviewed => "next_unreviewed",
+        }
+    }
+    fn from_name(name: &str) -> Result<Self> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.name() == name)
+            .ok_or_else(|| anyhow!("unknown action {name:?}"))
+    }
+
+    pub fn description(self) -> &'static str {
+        use Action::*;
+        match self {
+            Next => "Next image",
+            Previous => "Previous image",
+            Filter => "Toggle class filter",
+            Repeat => "Repeat boxes of previous image",
+            Clear => "Clear labels",
+            Skip => "Skip the highlighted box",
+            Advance => "Next image even with boxes missing",
+            Propagate => "Track boxes of previous image as proposals",
+            Accept => "Accept the focused proposal",
+            Reject => "Reject the focused proposal",
+            AcceptAll => "Accept all shown proposals",
+            Interpolate => "Interpolate from the previous keyframe",
+            PropagateTracks => "Take over track ids of previous image",
+            EditProposal => "Accept the focused proposal as the current class",
+            NextUnreviewed => "Next image with unreviewed proposals",
+        }
+    }
+}
+
+/// Keys bound to the actions of one mode.
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    keys: BTreeMap<Action, Vec<Key>>,
+    /// Actions bound in the keymap file rather than by default
+    configured: BTreeSet<Action>,
+}
+
+impl Bindings {
+    fn new(bindings: &[(Action, &[Key])]) -> Self {
+        Bindings {
+            keys: bindings
+                .iter()
+                .map(|(action, keys)| (*action, keys.to_vec()))
+                .collect(),
+            configured: BTreeSet::new(),
+        }
+    }
+    fn label_default() -> Self {
+        use Action::*;
+        Bindings::new(&[
+            (Next, &[Key::ArrowRight, Key::D]),
+            (Previous, &[Key::ArrowLeft, Key::A]),
+            (Filter, &[Key::F]),
+            (Repeat, &[Key::R]),
+            (Propagate, &[Key::P]),
+            (Accept, &[Key::Enter]),
+            (Reject, &[Key::Backspace]),
+            (AcceptAll, &[Key::Space]),
+            (Interpolate, &[Key::I]),
+            (PropagateTracks, &[Key::T]),
+            (EditProposal, &[Key::E]),
+            (NextUnreviewed, &[Key::U]),
+        ])
+    }
+    fn relabel_default() -> Self {
+        use Action::*;
+        Bindings::new(&[
+            (Next, &[Key::ArrowRight]),
+            (Previous, &[Key::ArrowLeft]),
+            (Repeat, &[Key::R]),
+            (Clear, &[Key::Delete]),
+            (Skip, &[Key::I]),
+            (Advance, &[Key::Enter]),
+        ])
+    }
+    fn overwrite(&mut self, file_bindings: HashMap<String, Vec<String>>) -> Result<()> {
+        for (action, names) in file_bindings {
+            let action = Action::from_name(&action)?;
+            let keys = names
+                .iter()
+                .map(|name| parse_key(name))
+                .collect::<Result<_>>()
+                .with_context(|| format!("in the binding of {}", action.name()))?;
+            self.keys.insert(action, keys);
+            self.configured.insert(action);
+        }
+        Ok(())
+    }
+
+    pub fn pressed(&self, ctx: &Context, action: Action) -> bool {
+        self.keys(action)
+            .iter()
+            .any(|key| ctx.input().key_pressed(*key))
+    }
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.keys
+            .get(&action)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (Action, &[Key])> {
+        self.keys
+            .iter()
+            .map(|(action, keys)| (*action, keys.as_slice()))
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    #[serde(default)]
+    label: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    relabel: HashMap<String, Vec<String>>,
+    /// class name to key sequence
+    #[serde(default)]
+    classes: HashMap<String, String>,
+}
+
+/// Key bindings of both modes and the class shortcuts, as configured in the keymap file.
+pub struct Keymap {
+    pub label: Bindings,
+    pub relabel: Bindings,
+    classes: HashMap<String, Vec<Key>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            label: Bindings::label_default(),
+            relabel: Bindings::relabel_default(),
+            classes: HashMap::new(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Loads the keymap from `path`, or from `keymap.toml` if it exists, otherwise uses the defaults.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_KEYMAP_FILE).is_file() => Path::new(DEFAULT_KEYMAP_FILE),
+            None => return Ok(Keymap::default()),
+        };
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read keymap {path:?}"))?;
+        let file: KeymapFile =
+            toml::from_str(&content).with_context(|| format!("invalid keymap {path:?}"))?;
+
+        let mut keymap = Keymap::default();
+        keymap.label.overwrite(file.label)?;
+        keymap.relabel.overwrite(file.relabel)?;
+        for (class, sequence) in file.classes {
+            let keys = parse_sequence(&sequence)
+                .with_context(|| format!("in the binding of class {class:?}"))?;
+            keymap.classes.insert(class, keys);
+        }
+        println!("Loaded keymap from {path:?}");
+        Ok(keymap)
+    }
+
+    /// The shortcuts of the classes of `L`, with the ones from the keymap file taking precedence.
+    ///
+    /// A default action key that starts a class shortcut, like `D` for the next image and
+    /// diamonds, is unbound from the action with a warning. Fails with a list of all other
+    /// conflicts if two bindings can not be told apart.
+    pub fn class_shortcuts<L: Label>(
+        &self,
+        bindings: &mut Bindings,
+    ) -> Result<HashMap<Vec<Key>, L>> {
+        let mut conflicts = vec![];
+        let mut class_keys: BTreeMap<String, (L, Vec<Key>)> = L::shortcuts()
+            .into_iter()
+            .map(|(keys, class)| (class.to_name(), (class, keys)))
+            .collect();
+        for class in L::all() {
+            if let Some(keys) = self.classes.get(&class.to_name()) {
+                class_keys.insert(class.to_name(), (class, keys.clone()));
+            }
+        }
+
+        let mut shortcuts = HashMap::new();
+        for (name, (class, keys)) in class_keys {
+            if let Some(other) = shortcuts.insert(keys.clone(), class) {
+                conflicts.push(format!(
+                    "classes {} and {name} are both bound to {}",
+                    other.to_name(),
+                    shortcut_text(&keys)
+                ));
+            }
+        }
+
+        for (action, keys) in bindings.keys.iter_mut() {
+            if bindings.configured.contains(action) {
+                continue;
+            }
+            keys.retain(|key| {
+                let Some((shortcut, class)) = shortcuts
+                    .iter()
+                    .filter(|(keys, _)| keys[0] == *key)
+                    .min_by_key(|(_, class)| class.to_usize())
+                else {
+                    return true;
+                };
+                println!(
+                    "Warning: {key:?} no longer triggers {action:?} because it starts the shortcut {} of class {}",
+                    shortcut_text(shortcut),
+                    class.to_name()
+                );
+                false
+            });
+        }
+
+        let actions: Vec<_> = bindings.iter().collect();
+        for (i, (action, keys)) in actions.iter().enumerate() {
+            for key in keys.iter() {
+                for (other_action, _) in actions[i + 1..]
+                    .iter()
+                    .filter(|(_, other_keys)| other_keys.contains(key))
+                {
+                    conflicts.push(format!(
+                        "{key:?} is bound to both {action:?} and {other_action:?}"
+                    ));
+                }
+                for (keys, class) in shortcuts.iter().filter(|(keys, _)| keys[0] == *key) {
+                    conflicts.push(format!(
+                        "{key:?} is bound to {action:?} and starts the shortcut {} of class {}",
+                        shortcut_text(keys),
+                        class.to_name()
+                    ));
+                }
+            }
+        }
+
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            bail!(
+                "conflicting key bindings, rebind them in {DEFAULT_KEYMAP_FILE} or the file passed with --keymap:\n  {}",
+                conflicts.join("\n  ")
+            );
+        }
+        Ok(shortcuts)
+    }
+}
+
+/// Whether `?` was typed this frame, which toggles the cheat sheet.
+pub fn cheat_sheet_pressed(ctx: &Context) -> bool {
+    ctx.input()
+        .events
+        .iter()
+        .any(|event| matches!(event, Event::Text(text) if text == "?"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::{Card, CardSuit, Suit};
+
+    fn configure(bindings: &mut Bindings, action: &str, keys: &[&str]) {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+        bindings
+            .overwrite(HashMap::from([(action.to_string(), keys)]))
+            .unwrap();
+    }
+
+    #[test]
+    fn default_bindings_fit_the_card_shortcuts() {
+        let keymap = Keymap::default();
+        let mut bindings = keymap.label.clone();
+        let shortcuts = keymap.class_shortcuts::<Card>(&mut bindings).unwrap();
+        assert_eq!(shortcuts.len(), Card::all().len());
+        assert_eq!(bindings.keys(Action::Next), [Key::ArrowRight, Key::D]);
+    }
+
+    #[test]
+    fn default_key_starting_a_shortcut_is_unbound() {
+        let keymap = Keymap::default();
+        let mut bindings = keymap.label.clone();
+        let shortcuts = keymap.class_shortcuts::<Suit>(&mut bindings).unwrap();
+        assert_eq!(shortcuts[&vec![Key::D]], Suit::Diamonds);
+        assert_eq!(bindings.keys(Action::Next), [Key::ArrowRight]);
+
+        // only the first key of a sequence matters
+        let mut bindings = keymap.label.clone();
+        keymap.class_shortcuts::<CardSuit>(&mut bindings).unwrap();
+        assert_eq!(bindings.keys(Action::Next), [Key::ArrowRight]);
+        assert_eq!(bindings.keys(Action::Previous), [Key::ArrowLeft, Key::A]);
+    }
+
+    #[test]
+    fn configured_key_starting_a_shortcut_conflicts() {
+        let keymap = Keymap::default();
+        let mut bindings = keymap.label.clone();
+        configure(&mut bindings, "next", &["D"]);
+        let error = keymap
+            .class_shortcuts::<Suit>(&mut bindings)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("D is bound to Next and starts the shortcut D"));
+    }
+
+    #[test]
+    fn actions_on_the_same_key_conflict() {
+        let keymap = Keymap::default();
+        let mut bindings = keymap.label.clone();
+        configure(&mut bindings, "filter", &["R"]);
+        let error = keymap
+            .class_shortcuts::<Card>(&mut bindings)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("R is bound to both Filter and Repeat"));
+    }
+
+    #[test]
+    fn classes_on_the_same_keys_conflict() {
+        let mut keymap = Keymap::default();
+        keymap.classes.insert("C".into(), vec![Key::H]);
+        let mut bindings = keymap.label.clone();
+        let error = keymap
+            .class_shortcuts::<Suit>(&mut bindings)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("classes C and H are both bound to H"));
+    }
+
+    #[test]
+    fn keymap_file_shortcut_replaces_the_default() {
+        let mut keymap = Keymap::default();
+        keymap.classes.insert("H".into(), vec![Key::X, Key::Num1]);
+        let mut bindings = keymap.label.clone();
+        let shortcuts = keymap.class_shortcuts::<Suit>(&mut bindings).unwrap();
+        assert_eq!(shortcuts[&vec![Key::X, Key::Num1]], Suit::Hearts);
+        assert!(!shortcuts.contains_key(&vec![Key::H]));
+    }
+
+    #[test]
+    fn key_sequences() {
+        assert_eq!(parse_sequence("H 1").unwrap(), [Key::H, Key::Num1]);
+        assert_eq!(parse_sequence("arrowright").unwrap(), [Key::ArrowRight]);
+        assert!(parse_sequence(" ").is_err());
+        assert!(parse_sequence("H Hyper").is_err());
+    }
+}
@@ -1,20 +1,399 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{anyhow, bail, Result};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use eframe::egui;
 use egui::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 mod dataset;
-use dataset::{BoundingBox, Card, Dataset, DatasetMovement, Label, YoloBB, YoloLabel};
+use dataset::{
+    BoundingBox, Card, CardSuit, Dataset, DatasetDirs, DatasetMovement, Label, Refine, Suit,
+    YoloBB, YoloLabel,
+};
 use image::{Rgba, RgbaImage};
 
+mod keymap;
+use keymap::{Action, Bindings, Keymap};
+
+mod agreement;
+
+mod compare;
+use compare::Compare;
+
+mod crops;
+use crops::CropShape;
+
+mod datayaml;
+use datayaml::DataYaml;
+
+mod detector;
+use detector::Detector;
+
+mod diff;
+
+mod eval;
+
+mod finalize;
+
+mod imagehash;
+
+mod interpolation;
+
+mod matching;
+use matching::{Assignment, Matcher};
+
+mod merge;
+use merge::Coordinates;
+
+mod order;
+use order::{Order, OrderKind};
+
+mod predictions;
+
+mod project;
+use project::Flag;
+
+mod propagation;
+
+mod proposals;
+use proposals::{Origin, Proposals};
+
+mod remap;
+
 mod relabeling;
 use relabeling::Relabeling;
 
+mod render;
+
+mod report;
+
+mod shortcuts;
+use shortcuts::{KeySequence, SEQUENCE_TIMEOUT};
+
+mod split;
+use split::SplitMode;
+
+mod tracks;
+
+mod widgets;
+
 #[derive(Subcommand)]
 enum Mode {
-    Label,
-    Relabel,
+    Label {
+        /// Set of classes to label with
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+        /// Give every new box a track id, to follow objects through a video
+        #[arg(long)]
+        tracks: bool,
+        /// Order to label the images in, by default by name
+        #[arg(long, value_enum)]
+        order: Option<OrderKind>,
+        /// Label in the order chosen last time with --order
+        #[arg(long, conflicts_with = "order")]
+        restore_order: bool,
+        /// Seed of the random order
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// File with an uncertainty score per image for the uncertainty order, as `name,score` lines
+        #[arg(long)]
+        scores: Option<PathBuf>,
+    },
+    /// Refine the labels into a finer set of classes
+    Relabel {
+        /// Directory the refined labels are written to
+        #[arg(long, default_value = "relabeled")]
+        output: PathBuf,
+        /// Minimal IoU of a refined box with its source box
+        #[arg(long, value_parser = matching::parse_iou, default_value_t = 0.95)]
+        iou: f32,
+        /// How refined boxes are paired with source boxes
+        #[arg(long, value_enum, default_value_t = Assignment::Greedy)]
+        assignment: Assignment,
+        /// Set of classes of the labels to refine
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        source: LabelSet,
+        /// Set of classes the labels are refined into
+        #[arg(long, value_enum, default_value_t = LabelSet::CardSuit)]
+        classes: LabelSet,
+    },
+    /// Move the refined labels of a relabeling into the dataset
+    Finalize {
+        /// Directory with the refined labels
+        #[arg(long, default_value = "relabeled")]
+        from: PathBuf,
+        /// Set of classes of the refined labels
+        #[arg(long, value_enum, default_value_t = LabelSet::CardSuit)]
+        classes: LabelSet,
+        /// Keep the labels of images that were not relabeled instead of removing them
+        #[arg(long)]
+        merge: bool,
+        /// Where the replaced labels are moved to
+        #[arg(long, default_value = "labels_backup")]
+        backup: PathBuf,
+    },
+    /// Rewrite the class ids of all labels with merge, drop and renumbering rules
+    Remap {
+        /// TOML file with the rules
+        #[arg(long)]
+        rules: PathBuf,
+        /// Set of classes the labels and rules are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+        /// Only print the changes, without writing any label file
+        #[arg(long)]
+        dry_run: bool,
+        /// Where to write the audit report of all changes
+        #[arg(long, default_value = "remap_audit.json")]
+        report: PathBuf,
+    },
+    /// Interpolate the boxes of the images between every two keyframes by their track ids
+    Interpolate {
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Propose boxes for all unlabeled images with an ONNX detector
+    Prelabel {
+        /// YOLOv8 style detector exported to ONNX
+        #[arg(long)]
+        model: PathBuf,
+        /// Set of classes of the model outputs
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+        /// Minimal confidence of a proposed box
+        #[arg(long, default_value_t = 0.25)]
+        confidence: f32,
+        /// IoU above which the less confident of two boxes of a class is dropped
+        #[arg(long, default_value_t = 0.45)]
+        nms_iou: f32,
+        /// ONNX Runtime library, by default found through ORT_DYLIB_PATH or the library path
+        #[arg(long)]
+        runtime: Option<PathBuf>,
+        /// Replace existing proposals
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Import the predictions of another tool as proposals to review
+    Import {
+        /// Directory with Yolo files with a confidence column, or a COCO results JSON file
+        #[arg(long)]
+        predictions: PathBuf,
+        /// COCO annotations with the image file names and category names of the results
+        #[arg(long)]
+        annotations: Option<PathBuf>,
+        /// Set of classes of the predictions
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Export the boxes with a track id as MOTChallenge ground truth
+    ExportMot {
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+        /// File to write
+        #[arg(long, default_value = "gt.txt")]
+        out: PathBuf,
+    },
+    /// Cut every box out of its image, into a directory per class
+    Crops {
+        /// Directory to write the crops to
+        #[arg(long, default_value = "crops")]
+        out: PathBuf,
+        /// Part of the box size added around each side of the box
+        #[arg(long, value_parser = crops::parse_padding, default_value_t = 0.0)]
+        padding: f32,
+        /// Length of the longer side of the crops, by default they keep their size
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        size: Option<u32>,
+        /// Shape of the crops
+        #[arg(long, value_enum, default_value_t = CropShape::Keep)]
+        shape: CropShape,
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Draw the boxes onto copies of the labeled images, or onto contact sheets of them
+    Render {
+        /// Directory to write the images to
+        #[arg(long, default_value = "render")]
+        out: PathBuf,
+        /// Image format to write
+        #[arg(long, value_enum, default_value_t = render::Format::Jpg)]
+        format: render::Format,
+        /// Only render the images with a box of this class, can be repeated
+        #[arg(long = "class")]
+        class_names: Vec<String>,
+        /// Put the images on contact sheets of COLUMNSxROWS images, e.g. 4x3
+        #[arg(long, value_parser = render::parse_grid)]
+        sheet: Option<(u32, u32)>,
+        /// Size in pixels of the square each image gets on a contact sheet
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 320)]
+        cell: u32,
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Write a self-contained HTML page with statistics, galleries and warnings of the labels
+    Report {
+        /// File to write
+        #[arg(long, default_value = "report.html")]
+        out: PathBuf,
+        /// Maximal number of images in the gallery of a class
+        #[arg(long, default_value_t = 12)]
+        per_class: usize,
+        /// Size in pixels of the thumbnails
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 240)]
+        thumbnail: u32,
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Compare two label directories of the same images
+    Diff {
+        /// First label directory
+        #[arg(long)]
+        a: PathBuf,
+        /// Second label directory
+        #[arg(long)]
+        b: PathBuf,
+        /// Minimal IoU of two boxes to be the same box
+        #[arg(long, value_parser = matching::parse_iou, default_value_t = 0.5)]
+        iou: f32,
+        /// IoU below which the same box counts as moved
+        #[arg(long, value_parser = matching::parse_iou, default_value_t = 0.9)]
+        moved: f32,
+        /// How the boxes of the two directories are paired
+        #[arg(long, value_enum, default_value_t = Assignment::Hungarian)]
+        assignment: Assignment,
+        /// Show both labels on top of each other in the app instead of printing the differences
+        #[arg(long)]
+        gui: bool,
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Measure how well the labels of two annotators agree on the images both labeled
+    Agreement {
+        /// Label directory of the annotator to measure
+        #[arg(long)]
+        a: PathBuf,
+        /// Label directory of the annotator to measure against
+        #[arg(long)]
+        b: PathBuf,
+        /// Minimal IoUs of two boxes to be the same box
+        #[arg(long, value_parser = matching::parse_iou, value_delimiter = ',', default_value = "0.5,0.75")]
+        iou: Vec<f32>,
+        /// How the boxes of the two directories are paired
+        #[arg(long, value_enum, default_value_t = Assignment::Hungarian)]
+        assignment: Assignment,
+        /// Number of the most disputed images to list
+        #[arg(long, default_value_t = 10)]
+        disputed: usize,
+        /// Where to write the agreement as JSON
+        #[arg(long, default_value = "agreement.json")]
+        report: PathBuf,
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Merge the labels of several annotators into consensus labels
+    Merge {
+        /// Label directories of the annotators
+        #[arg(long, value_delimiter = ',', required = true)]
+        from: Vec<PathBuf>,
+        /// Labels directory to write the consensus to, the one of the dataset by default
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Minimal IoU of two boxes to be the same object
+        #[arg(long, value_parser = matching::parse_iou, default_value_t = 0.5)]
+        iou: f32,
+        /// How the coordinates of the boxes of an object are combined
+        #[arg(long, value_enum, default_value_t = Coordinates::Median)]
+        coordinates: Coordinates,
+        /// Minimal number of annotators that drew an object, more than half by default
+        #[arg(long)]
+        min_votes: Option<usize>,
+        /// Share of the objects of an image the annotators may disagree on before it is flagged
+        #[arg(long, default_value_t = 0.0)]
+        max_disagreement: f32,
+        /// Replace existing labels in the output directory
+        #[arg(long)]
+        overwrite: bool,
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Evaluate the predictions of a detector against the labels as ground truth
+    Eval {
+        /// Directory with Yolo files with a confidence column, or a COCO results JSON file
+        #[arg(long)]
+        pred: PathBuf,
+        /// COCO annotations with the image file names and category names of the results
+        #[arg(long)]
+        annotations: Option<PathBuf>,
+        /// Minimal confidence of the predictions in the confusion matrix and the false positives
+        #[arg(long, default_value_t = 0.25)]
+        confidence: f32,
+        /// Number of the worst false positives and negatives to print
+        #[arg(long, default_value_t = 10)]
+        worst: usize,
+        /// Directory to write the evaluation and the lists of images to
+        #[arg(long, default_value = "eval")]
+        out: PathBuf,
+        /// Set of classes of the labels and predictions
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+    /// Split the labeled images into train, val and test sets in the Ultralytics layout
+    Split {
+        /// Parts of the train, val and optional test split
+        #[arg(long, value_delimiter = ',', default_value = "0.8,0.1,0.1")]
+        ratios: Vec<f32>,
+        /// Seed of the random assignment
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Directory to write the splits and data.yaml to
+        #[arg(long, default_value = "dataset")]
+        out: PathBuf,
+        /// How the images and labels get into the splits
+        #[arg(long, value_enum, default_value_t = SplitMode::Symlink)]
+        mode: SplitMode,
+        /// Maximal number of differing bits of the image hashes of near-duplicates
+        #[arg(long, default_value_t = 4)]
+        duplicate_distance: u32,
+        /// Set of classes the labels are in
+        #[arg(long, value_enum, default_value_t = LabelSet::Card)]
+        classes: LabelSet,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LabelSet {
+    Card,
+    Suit,
+    CardSuit,
+}
+
+/// Evaluates `$body` with `$L` being the label type of the label set.
+macro_rules! with_label_set {
+    ($set:expr, $L:ident => $body:expr) => {
+        match $set {
+            LabelSet::Card => {
+                type $L = Card;
+                $body
+            }
+            LabelSet::Suit => {
+                type $L = Suit;
+                $body
+            }
+            LabelSet::CardSuit => {
+                type $L = CardSuit;
+                $body
+            }
+        }
+    };
 }
 
 #[derive(Parser)]
@@ -22,25 +401,404 @@ enum Mode {
 struct Cli {
     #[command(subcommand)]
     mode: Mode,
+    /// Key bindings file, defaults to keymap.toml in the working directory if it exists
+    #[arg(long, global = true)]
+    keymap: Option<PathBuf>,
+    /// Ultralytics data.yaml of the dataset, instead of the images and labels in ./input
+    #[arg(long, global = true)]
+    data: Option<PathBuf>,
+    /// Split of the data.yaml to open
+    #[arg(long, global = true, default_value = "train")]
+    split: String,
 }
 
-// Here is a simplified version of the code:
+impl Mode {
+    /// The label set of the modes that take one.
+    fn classes_mut(&mut self) -> Option<&mut LabelSet> {
+        match self {
+            Mode::Label { classes, .. }
+            | Mode::Finalize { classes, .. }
+            | Mode::Remap { classes, .. }
+            | Mode::Interpolate { classes }
+            | Mode::Prelabel { classes, .. }
+            | Mode::Import { classes, .. }
+            | Mode::ExportMot { classes, .. }
+            | Mode::Crops { classes, .. }
+            | Mode::Render { classes, .. }
+            | Mode::Report { classes, .. }
+            | Mode::Diff { classes, .. }
+            | Mode::Agreement { classes, .. }
+            | Mode::Merge { classes, .. }
+            | Mode::Eval { classes, .. }
+            | Mode::Split { classes, .. } => Some(classes),
+            Mode::Relabel { .. } => None,
+        }
+    }
+}
 
-fn main() {
-    let cli = Cli::parse();
+/// Opens a split of a `data.yaml`. Its class names pick the label set, unless `--classes` was
+/// given. With `rewrite_names` the class names are then rewritten to the ones of that label set,
+/// otherwise they have to be the ones of that label set already.
+fn open_data_yaml(
+    path: &Path,
+    split: &str,
+    classes: Option<&mut LabelSet>,
+    explicit_classes: bool,
+    rewrite_names: bool,
+) -> Result<DatasetDirs> {
+    let mut data = DataYaml::load(path)?;
+    if let Some(classes) = classes {
+        let matching = |set: LabelSet| with_label_set!(set, L => data.matches::<L>());
+        let not_a_label_set = || {
+            let hint = if rewrite_names {
+                "choose one with --classes to rewrite them"
+            } else {
+                "rewrite them with the --classes of label or remap"
+            };
+            anyhow!("the class names of {path:?} are not those of a label set, {hint}")
+        };
+        if !explicit_classes {
+            *classes = LabelSet::value_variants()
+                .iter()
+                .copied()
+                .find(|set| matching(*set))
+                .ok_or_else(not_a_label_set)?;
+        } else if !matching(*classes) {
+            if !rewrite_names {
+                return Err(not_a_label_set());
+            }
+            with_label_set!(*classes, L => data.set_label_set::<L>());
+            data.save_names(path)?;
+            println!("Updated the class names in {path:?}");
+        }
+    }
+    data.split_dirs(path, split)
+}
+
+fn main() -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)?;
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(1920.0, 1080.0)),
         ..Default::default()
     };
 
+    let keymap = Keymap::load(cli.keymap.as_deref())?;
+    let dirs = match &cli.data {
+        Some(path) => {
+            // only labeling and remapping change what the class ids mean
+            let rewrite_names = matches!(cli.mode, Mode::Label { .. } | Mode::Remap { .. });
+            let classes = cli.mode.classes_mut();
+            let explicit_classes = classes.is_some()
+                && matches.subcommand().is_some_and(|(_, m)| {
+                    m.value_source("classes") == Some(ValueSource::CommandLine)
+                });
+            open_data_yaml(path, &cli.split, classes, explicit_classes, rewrite_names)?
+        }
+        None => DatasetDirs::default(),
+    };
     let app = match cli.mode {
-        Mode::Label => Box::new(Boundrs::build_app) as eframe::AppCreator,
-        Mode::Relabel => Box::new(Relabeling::build_app) as eframe::AppCreator,
+        Mode::Label {
+            classes,
+            tracks,
+            order,
+            restore_order,
+            seed,
+            scores,
+        } => {
+            let order = order
+                .map(|kind| Order::new(kind, seed, scores))
+                .transpose()?;
+            with_label_set!(classes, L => label_app::<L>(&keymap, &dirs, tracks, order, restore_order)?)
+        }
+        Mode::Relabel {
+            output,
+            iou,
+            assignment,
+            source,
+            classes,
+        } => {
+            let matcher = Matcher::new(iou, assignment);
+            match (source, classes) {
+                (LabelSet::Card, LabelSet::CardSuit) => {
+                    relabel_app::<Card, CardSuit>(&keymap, &dirs, &output, matcher)?
+                }
+                (LabelSet::Suit, LabelSet::CardSuit) => {
+                    relabel_app::<Suit, CardSuit>(&keymap, &dirs, &output, matcher)?
+                }
+                _ => {
+                    let name =
+                        |set: LabelSet| set.to_possible_value().unwrap().get_name().to_string();
+                    bail!(
+                        "{} can not be refined into {}, only card and suit into card-suit",
+                        name(source),
+                        name(classes)
+                    );
+                }
+            }
+        }
+        Mode::Finalize {
+            from,
+            classes,
+            merge,
+            backup,
+        } => {
+            return with_label_set!(classes, L => {
+                let mut dataset = Dataset::<L>::open(&dirs)?;
+                if !from.is_dir() {
+                    bail!("label directory {from:?} not found");
+                }
+                let refined = Dataset::<L>::from_dirs(&dirs.images, &from)?;
+                finalize::finalize(&mut dataset, &refined, merge, &backup)
+            });
+        }
+        Mode::Remap {
+            rules,
+            classes,
+            dry_run,
+            report,
+        } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                remap::remap(&dataset, &rules, dry_run, &report)
+            });
+        }
+        Mode::Interpolate { classes } => {
+            return with_label_set!(classes, L => {
+                let mut dataset = Dataset::<L>::open(&dirs)?;
+                interpolation::interpolate_all(&mut dataset)
+            });
+        }
+        Mode::Prelabel {
+            model,
+            classes,
+            confidence,
+            nms_iou,
+            runtime,
+            overwrite,
+        } => {
+            let mut detector = Detector::load(&model, runtime.as_deref(), confidence, nms_iou)?;
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                detector::prelabel(&dataset, &mut detector, overwrite)
+            });
+        }
+        Mode::Import {
+            predictions,
+            annotations,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                predictions::import(&dataset, &predictions, annotations.as_deref())
+            });
+        }
+        Mode::ExportMot { classes, out } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                tracks::export_mot(&dataset, &out)
+            });
+        }
+        Mode::Crops {
+            out,
+            padding,
+            size,
+            shape,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                crops::export_crops(&dataset, &out, padding, size, shape)
+            });
+        }
+        Mode::Render {
+            out,
+            format,
+            class_names,
+            sheet,
+            cell,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                render::render(&dataset, &out, format, &class_names, sheet, cell)
+            });
+        }
+        Mode::Report {
+            out,
+            per_class,
+            thumbnail,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                report::report(&dataset, &out, per_class, thumbnail)
+            });
+        }
+        Mode::Diff {
+            a,
+            b,
+            iou,
+            moved,
+            assignment,
+            gui,
+            classes,
+        } => {
+            let matcher = Matcher::new(iou, assignment);
+            if gui {
+                with_label_set!(classes, L => compare_app::<L>(&keymap, &dirs, &a, &b, matcher, moved)?)
+            } else {
+                return with_label_set!(classes, L => {
+                    let (a, b) = open_label_dirs::<L>(&dirs, &a, &b)?;
+                    diff::diff(&a, &b, &matcher, moved)
+                });
+            }
+        }
+        Mode::Agreement {
+            a,
+            b,
+            iou,
+            assignment,
+            disputed,
+            report,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let (a, b) = open_label_dirs::<L>(&dirs, &a, &b)?;
+                agreement::agreement(&a, &b, &iou, assignment, disputed, &report)
+            });
+        }
+        Mode::Merge {
+            from,
+            out,
+            iou,
+            coordinates,
+            min_votes,
+            max_disagreement,
+            overwrite,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let annotators = from
+                    .iter()
+                    .map(|dir| {
+                        if !dir.is_dir() {
+                            bail!("label directory {dir:?} not found");
+                        }
+                        Dataset::<L>::from_dirs(&dirs.images, dir)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let out = out.unwrap_or_else(|| dirs.labels.clone());
+                std::fs::create_dir_all(&out)?;
+                let mut out = Dataset::<L>::with_labels_dir(&dirs, &out)?;
+                merge::merge(&mut out, &annotators, iou, coordinates, min_votes, max_disagreement, overwrite)
+            });
+        }
+        Mode::Eval {
+            pred,
+            annotations,
+            confidence,
+            worst,
+            out,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                eval::eval(&dataset, &pred, annotations.as_deref(), confidence, worst, &out)
+            });
+        }
+        Mode::Split {
+            ratios,
+            seed,
+            out,
+            mode,
+            duplicate_distance,
+            classes,
+        } => {
+            return with_label_set!(classes, L => {
+                let dataset = Dataset::<L>::open(&dirs)?;
+                split::split(&dataset, &out, &ratios, seed, mode, duplicate_distance)
+            });
+        }
     };
 
     eframe::run_native("Show an image with eframe/egui", options, app);
+    Ok(())
 }
 
+fn label_app<L: Label>(
+    keymap: &Keymap,
+    dirs: &DatasetDirs,
+    tracks: bool,
+    order: Option<Order>,
+    restore_order: bool,
+) -> Result<eframe::AppCreator> {
+    let mut bindings = keymap.label.clone();
+    let shortcuts = keymap.class_shortcuts::<L>(&mut bindings)?;
+    let mut dataset = Dataset::<L>::open(dirs)?;
+    if let Some(order) = order {
+        dataset.set_order(order)?;
+    } else if restore_order {
+        dataset.restore_order();
+    }
+    if let Some(order) = dataset.order() {
+        println!("Images ordered {order}, interpolation is disabled as it needs the name order");
+    }
+    Ok(Box::new(move |cc| {
+        Boundrs::build_app(cc, bindings, shortcuts, dataset, tracks)
+    }))
+}
+
+fn relabel_app<S: Refine<T>, T: Label>(
+    keymap: &Keymap,
+    dirs: &DatasetDirs,
+    output: &Path,
+    matcher: Matcher,
+) -> Result<eframe::AppCreator> {
+    let mut bindings = keymap.relabel.clone();
+    let shortcuts = keymap.class_shortcuts::<S::Detail>(&mut bindings)?;
+    let old_dataset = Dataset::<S>::open(dirs)?;
+    let new_dataset = Dataset::<T>::with_labels_dir(dirs, output)?;
+    Ok(Box::new(move |cc| {
+        Relabeling::build_app(cc, bindings, shortcuts, old_dataset, new_dataset, matcher)
+    }))
+}
+
+/// The images of `dirs` with the labels of the directories `a` and `b` each.
+fn open_label_dirs<L: Label>(
+    dirs: &DatasetDirs,
+    a: &Path,
+    b: &Path,
+) -> Result<(Dataset<L>, Dataset<L>)> {
+    for labels in [a, b] {
+        if !labels.is_dir() {
+            bail!("label directory {labels:?} not found");
+        }
+    }
+    Ok((
+        Dataset::from_dirs(&dirs.images, a)?,
+        Dataset::from_dirs(&dirs.images, b)?,
+    ))
+}
+
+fn compare_app<L: Label>(
+    keymap: &Keymap,
+    dirs: &DatasetDirs,
+    a: &Path,
+    b: &Path,
+    matcher: Matcher,
+    moved_iou: f32,
+) -> Result<eframe::AppCreator> {
+    let bindings = keymap.label.clone();
+    let (a, b) = open_label_dirs::<L>(dirs, a, b)?;
+    Ok(Box::new(move |cc| {
+        Compare::build_app(cc, bindings, a, b, matcher, moved_iou)
+    }))
+}
+
+/// Minimal IoU of a box with the box of the previous image whose track id it gets.
+const TRACK_IOU: f32 = 0.3;
+
 #[derive(Debug, Clone, Copy)]
 enum BBoxInput {
     None,
@@ -48,23 +806,45 @@ enum BBoxInput {
     Finished(Pos2, Pos2),
 }
 
-struct Boundrs {
+struct Boundrs<L: Label> {
     image_texture: egui::TextureHandle,
     mask_texture: egui::TextureHandle,
     bbox_input: BBoxInput,
-    dataset: Dataset<Card>,
-    current_class: Card,
+    dataset: Dataset<L>,
+    current_class: L,
     image_rect: Rect,
     filter: bool,
     filter_opacity: u8,
-    shown_classes: HashSet<Card>,
-    current_label: YoloLabel<Card>,
+    shown_classes: HashSet<L>,
+    current_label: YoloLabel<L>,
+    search_query: String,
+    // index into current_label of the box hovered in the boxes panel
+    hovered_bb: Option<usize>,
+    key_sequence: KeySequence,
+    bindings: Bindings,
+    shortcuts: HashMap<Vec<Key>, L>,
+    show_cheat_sheet: bool,
+    proposals: Proposals<L>,
+    // index into proposals of the proposal hovered in the proposals panel
+    hovered_proposal: Option<usize>,
+    // whether new boxes get a track id
+    tracks: bool,
+    next_track: u32,
+    color_by_track: bool,
+    // result of the last interpolation
+    notice: Option<String>,
 }
 
-impl Boundrs {
+impl<L: Label> Boundrs<L> {
     // TODO error handling
-    fn build_app(cc: &eframe::CreationContext<'_>) -> Box<dyn eframe::App> {
-        let dataset = Dataset::from_input_dir().unwrap();
+    fn build_app(
+        cc: &eframe::CreationContext<'_>,
+        bindings: Bindings,
+        shortcuts: HashMap<Vec<Key>, L>,
+        dataset: Dataset<L>,
+        tracks: bool,
+    ) -> Box<dyn eframe::App> {
+        let next_track = dataset.max_track().unwrap().map_or(0, |max| max + 1);
         let image = dataset.current_image().unwrap();
         let image_texture =
             cc.egui_ctx
@@ -77,28 +857,49 @@ impl Boundrs {
         let mask_texture = cc
             .egui_ctx
             .load_texture("mask", mask, egui::TextureFilter::Linear);
+        let mut proposals = Proposals::new();
+        let (_, i, _) = dataset.get_progress();
+        let notice = match dataset.proposals(i) {
+            Ok(predicted) => {
+                proposals.set(Origin::Predicted, predicted);
+                None
+            }
+            Err(e) => Some(format!("{e:#}")),
+        };
 
         Box::new(Self {
             image_texture,
             mask_texture,
             bbox_input: BBoxInput::None,
-            dataset: Dataset::from_input_dir().unwrap(),
-            current_class: Card::V2,
+            dataset,
+            current_class: L::all()[0],
             image_rect: Rect::NOTHING,
             filter: false,
             filter_opacity,
             shown_classes,
             current_label: current_bbs,
+            search_query: String::new(),
+            hovered_bb: None,
+            key_sequence: KeySequence::new(SEQUENCE_TIMEOUT),
+            bindings,
+            shortcuts,
+            show_cheat_sheet: false,
+            proposals,
+            hovered_proposal: None,
+            tracks,
+            next_track,
+            color_by_track: tracks,
+            notice,
         })
     }
 }
 
-fn pos_inside_label_box(label: &YoloLabel<Card>, pos: Pos2, size: Vec2) -> bool {
+fn pos_inside_label_box<L: Label>(label: &YoloLabel<L>, pos: Pos2, size: Vec2) -> bool {
     label.iter().any(|l| l.rect(size).contains(pos))
 }
-fn generate_mask(
-    label: &YoloLabel<Card>,
-    shown_classes: &HashSet<Card>,
+fn generate_mask<L: Label>(
+    label: &YoloLabel<L>,
+    shown_classes: &HashSet<L>,
     size: Vec2,
     opacity: u8,
 ) -> ColorImage {
@@ -121,7 +922,7 @@ fn generate_mask(
     ColorImage::from_rgba_unmultiplied([width, height], pixels.as_slice())
 }
 
-impl Boundrs {
+impl<L: Label> Boundrs<L> {
     fn to_img_coordinates(&self, pos: Pos2) -> Pos2 {
         (pos - self.image_rect.left_top()).to_pos2()
     }
@@ -134,7 +935,10 @@ impl Boundrs {
         self.current_label
             .retain(|label| !label.rect(size).contains(pos));
     }
-    pub fn add_bb(&mut self, bb: YoloBB<Card>) {
+    pub fn add_bb(&mut self, mut bb: YoloBB<L>) {
+        if self.tracks {
+            bb.set_track(Some(self.new_track()));
+        }
         self.current_label.push(bb)
     }
 
@@ -143,6 +947,83 @@ impl Boundrs {
         self.current_label = yolo_label;
         Ok(())
     }
+    /// Proposes the boxes of the previous image, tracked to where they moved in this image.
+    pub fn propagate_bbs(&mut self) -> Result<()> {
+        let previous = self.dataset.previous_image()?;
+        let current = self.dataset.current_image()?;
+        let label = self.dataset.previous_label()?;
+        self.proposals.set(
+            Origin::Tracked,
+            propagation::propagate(&previous, &current, &label),
+        );
+        Ok(())
+    }
+    fn new_track(&mut self) -> u32 {
+        self.next_track += 1;
+        self.next_track - 1
+    }
+    /// Carries the track ids of the previous image over to the matching boxes of this one.
+    fn propagate_tracks(&mut self) -> Result<()> {
+        let previous = self.dataset.previous_label()?;
+        let matcher = Matcher::new(TRACK_IOU, Assignment::Hungarian);
+        tracks::propagate_ids(
+            &previous,
+            &mut self.current_label,
+            &matcher,
+            &mut self.next_track,
+        );
+        Ok(())
+    }
+    /// Makes this image a keyframe and interpolates the images since the previous keyframe.
+    fn interpolate(&mut self) -> Result<String> {
+        // the images between two keyframes are only the frames between them in name order
+        if let Some(order) = self.dataset.order() {
+            return Ok(format!(
+                "Interpolation needs the images in name order, they are ordered {order}"
+            ));
+        }
+        let (_, i, _) = self.dataset.get_progress();
+        self.dataset.save_label_at(i, self.current_label.clone())?;
+        self.dataset.update_current_state(|state| {
+            state.flags.remove(&Flag::Interpolated);
+        })?;
+        let Some(from) = interpolation::previous_keyframe(&self.dataset, i) else {
+            return Ok("No keyframe before this image".to_string());
+        };
+        let boxes = interpolation::interpolate(&mut self.dataset, from, i)?;
+        Ok(format!(
+            "Interpolated {boxes} boxes into {} images",
+            i - from - 1
+        ))
+    }
+    fn accept_proposals(&mut self, bbs: Vec<YoloBB<L>>, ctx: &Context) {
+        self.current_label.extend(bbs);
+        self.hovered_proposal = None;
+        self.update_mask(ctx);
+    }
+    fn handle_proposal_keys(&mut self, ctx: &Context) {
+        if self.bindings.pressed(ctx, Action::Propagate) {
+            self.propagate_bbs().unwrap();
+        }
+        if self.bindings.pressed(ctx, Action::AcceptAll) {
+            let bbs = self.proposals.accept_visible();
+            self.accept_proposals(bbs, ctx);
+        }
+        let Some(focused) = self.proposals.focused() else {
+            return;
+        };
+        if self.bindings.pressed(ctx, Action::EditProposal) {
+            self.proposals.set_class(focused, self.current_class);
+            let bbs = self.proposals.accept(focused).into_iter().collect();
+            self.accept_proposals(bbs, ctx);
+        } else if self.bindings.pressed(ctx, Action::Accept) {
+            let bbs = self.proposals.accept(focused).into_iter().collect();
+            self.accept_proposals(bbs, ctx);
+        } else if self.bindings.pressed(ctx, Action::Reject) {
+            self.proposals.reject(focused);
+            self.hovered_proposal = None;
+        }
+    }
     fn remove_bbs(&mut self, pos: Pos2) {
         self.remove_labels(pos);
     }
@@ -184,14 +1065,13 @@ impl Boundrs {
                     self.image_texture.size_vec2(),
                     class,
                 );
-                println!("{:?}", label);
                 self.add_bb(label);
                 self.update_mask(ui.ctx());
                 BBoxInput::None
             }
         };
     }
-    fn draw_label_text(&self, painter: &Painter, text_pos: Pos2, class: Card) {
+    fn draw_label_text(&self, painter: &Painter, text_pos: Pos2, class: L) {
         painter.rect(
             Rect::from_two_pos(text_pos, text_pos + [40.0, -35.0].into()),
             Rounding::none(),
@@ -210,7 +1090,10 @@ impl Boundrs {
         let painter = ui.painter();
         let size = self.image_rect.size();
         for bb in &self.current_label {
-            let color = bb.class().color();
+            let color = match bb.track() {
+                Some(track) if self.color_by_track => tracks::track_color(track),
+                _ => bb.class().color(),
+            };
             // TODO improve
             let screen_rect = [
                 self.to_screen_coordinates(bb.rect(size).left_top()),
@@ -220,7 +1103,30 @@ impl Boundrs {
             painter.rect_stroke(screen_rect, Rounding::none(), Stroke::new(2.0, color));
             let text_pos = screen_rect.left_bottom();
             self.draw_label_text(painter, text_pos, bb.class());
+            if let Some(track) = bb.track() {
+                painter.text(
+                    text_pos + vec2(45.0, 0.0),
+                    Align2::LEFT_BOTTOM,
+                    format!("#{track}"),
+                    FontId::monospace(25.0),
+                    color,
+                );
+            }
+        }
+        if let Some(bb) = self.hovered_bb.and_then(|i| self.current_label.get(i)) {
+            let screen_rect = [
+                self.to_screen_coordinates(bb.rect(size).left_top()),
+                self.to_screen_coordinates(bb.rect(size).right_bottom()),
+            ]
+            .into();
+            painter.rect_stroke(
+                screen_rect,
+                Rounding::none(),
+                Stroke::new(8.0, Color32::WHITE),
+            );
         }
+        self.proposals
+            .draw(painter, self.image_rect, self.hovered_proposal);
     }
     fn draw_guide(&self, ui: &mut Ui, pos: Pos2) {
         let painter = ui.painter();
@@ -231,6 +1137,16 @@ impl Boundrs {
         painter.hline(0.0..=w_size.x, pos.y, stroke);
         painter.vline(pos.x, 0.0..=w_size.y, stroke);
         self.draw_label_text(painter, pos, self.current_class);
+        let pending = self.key_sequence.pending();
+        if !pending.is_empty() {
+            painter.text(
+                pos + vec2(45.0, 0.0),
+                Align2::LEFT_BOTTOM,
+                format!("{} …", widgets::shortcut_text(pending)),
+                FontId::monospace(35.0),
+                Color32::WHITE,
+            );
+        }
     }
     fn draw_partial_box(&self, ui: &mut Ui) {
         if let BBoxInput::Partial(pos) = self.bbox_input {
@@ -255,18 +1171,213 @@ impl Boundrs {
         }
     }
 
-    fn classes_pressed(&self, ctx: &Context) -> HashSet<Card> {
-        let mut classes = HashSet::new();
-        for (keys, class) in Card::shortcuts() {
-            if keys.iter().all(|key| ctx.input().key_pressed(*key)) {
-                classes.insert(class);
+    fn boxes_window(&mut self, ctx: &Context) {
+        let size = self.image_texture.size_vec2();
+        let mut hovered = None;
+        let mut deleted = None;
+        let mut changed = None;
+        // box index and its new track id, None for a fresh one
+        let mut track_changed = None;
+        egui::Window::new("Boxes").show(ctx, |ui| {
+            if self.current_label.is_empty() {
+                ui.label("No boxes in this image");
+            }
+            for (i, bb) in self.current_label.iter().enumerate() {
+                let rect = bb.rect(size);
+                let row = ui.horizontal(|ui| {
+                    let mut class = bb.class();
+                    egui::ComboBox::from_id_source(("box class", i))
+                        .width(50.0)
+                        .selected_text(RichText::new(class.to_name()).color(class.color()))
+                        .show_ui(ui, |ui| {
+                            for c in L::all() {
+                                ui.selectable_value(&mut class, c, c.to_name());
+                            }
+                        });
+                    if class != bb.class() {
+                        changed = Some((i, class));
+                    }
+                    ui.monospace(format!(
+                        "({:>4.0}, {:>4.0}) {:>4.0}x{:<4.0}",
+                        rect.left(),
+                        rect.top(),
+                        rect.width(),
+                        rect.height(),
+                    ));
+                    match bb.track() {
+                        Some(mut track) => {
+                            let drag = DragValue::new(&mut track).prefix("#");
+                            if ui.add(drag).changed() {
+                                track_changed = Some((i, Some(track)));
+                            }
+                        }
+                        None => {
+                            if ui
+                                .small_button("Track")
+                                .on_hover_text("Give the box a new track id")
+                                .clicked()
+                            {
+                                track_changed = Some((i, None));
+                            }
+                        }
+                    }
+                    if ui.small_button("Delete").clicked() {
+                        deleted = Some(i);
+                    }
+                });
+                if row.response.hovered() {
+                    hovered = Some(i);
+                }
+            }
+        });
+        self.hovered_bb = hovered;
+        if let Some((i, class)) = changed {
+            self.current_label[i].set_class(class);
+            self.update_mask(ctx);
+        }
+        if let Some((i, track)) = track_changed {
+            let track = track.unwrap_or_else(|| self.new_track());
+            self.next_track = self.next_track.max(track + 1);
+            self.current_label[i].set_track(Some(track));
+        }
+        if let Some(i) = deleted {
+            self.current_label.remove(i);
+            self.hovered_bb = None;
+            self.update_mask(ctx);
+        }
+    }
+
+    fn proposals_window(&mut self, ctx: &Context) {
+        if self.proposals.is_empty() {
+            self.hovered_proposal = None;
+            return;
+        }
+        let mut hovered = None;
+        let mut accepted = None;
+        let mut rejected = None;
+        let mut changed = None;
+        let (mut accept_all, mut reject_all) = (false, false);
+        egui::Window::new("Proposals").show(ctx, |ui| {
+            for (i, proposal) in self.proposals.visible() {
+                let row = ui.horizontal(|ui| {
+                    let mut class = proposal.bb.class();
+                    egui::ComboBox::from_id_source(("proposal class", i))
+                        .width(50.0)
+                        .selected_text(RichText::new(class.to_name()).color(class.color()))
+                        .show_ui(ui, |ui| {
+                            for c in L::all() {
+                                ui.selectable_value(&mut class, c, c.to_name());
+                            }
+                        });
+                    if class != proposal.bb.class() {
+                        changed = Some((i, class));
+                    }
+                    ui.monospace(format!("{:>3.0}%", proposal.confidence * 100.0));
+                    if ui.small_button("Accept").clicked() {
+                        accepted = Some(i);
+                    }
+                    if ui.small_button("Reject").clicked() {
+                        rejected = Some(i);
+                    }
+                });
+                if row.response.hovered() {
+                    hovered = Some(i);
+                }
             }
+            let hidden = self.proposals.hidden();
+            if hidden > 0 {
+                ui.label(format!("{hidden} less confident proposals are hidden"));
+            }
+            ui.horizontal(|ui| {
+                ui.label("Min confidence");
+                ui.add(Slider::new(&mut self.proposals.min_confidence, 0.0..=1.0));
+            });
+            ui.horizontal(|ui| {
+                accept_all = ui.button("Accept shown").clicked();
+                reject_all = ui.button("Reject all").clicked();
+            });
+        });
+        self.hovered_proposal = hovered;
+        if let Some((i, class)) = changed {
+            self.proposals.set_class(i, class);
+        }
+        if let Some(i) = accepted {
+            let bbs = self.proposals.accept(i).into_iter().collect();
+            self.accept_proposals(bbs, ctx);
+        }
+        if let Some(i) = rejected {
+            self.proposals.reject(i);
+            self.hovered_proposal = None;
+        }
+        if accept_all {
+            let bbs = self.proposals.accept_visible();
+            self.accept_proposals(bbs, ctx);
+        }
+        if reject_all {
+            self.proposals.clear();
+            self.hovered_proposal = None;
+        }
+    }
+
+    fn classes_window(&mut self, ctx: &Context) {
+        let shortcuts: HashMap<L, Vec<Key>> = self
+            .shortcuts
+            .iter()
+            .map(|(keys, class)| (*class, keys.clone()))
+            .collect();
+        let mut clicked = None;
+        egui::Window::new("Classes").show(ctx, |ui| {
+            egui::Grid::new("classes legend")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("");
+                    ui.label("Class");
+                    ui.label("Key");
+                    ui.label("Count");
+                    ui.end_row();
+                    for class in L::all() {
+                        widgets::color_swatch(ui, class.color());
+                        let selected = if self.filter {
+                            self.shown_classes.contains(&class)
+                        } else {
+                            self.current_class == class
+                        };
+                        if ui.selectable_label(selected, class.to_name()).clicked() {
+                            clicked = Some(class);
+                        }
+                        let keys = shortcuts
+                            .get(&class)
+                            .map(|keys| widgets::shortcut_text(keys));
+                        ui.monospace(keys.unwrap_or_default());
+                        let count = self
+                            .current_label
+                            .iter()
+                            .filter(|bb| bb.class() == class)
+                            .count();
+                        ui.label(count.to_string());
+                        ui.end_row();
+                    }
+                });
+        });
+        if let Some(class) = clicked {
+            self.select_classes(HashSet::from([class]), ctx);
         }
-        classes
+    }
+
+    fn classes_pressed(&mut self, ctx: &Context) -> HashSet<L> {
+        self.key_sequence
+            .update(ctx, &self.shortcuts)
+            .into_iter()
+            .collect()
     }
 
     fn handle_class_keys(&mut self, ctx: &Context) {
         let classes = self.classes_pressed(ctx);
+        self.select_classes(classes, ctx);
+    }
+
+    /// Toggles the classes in the filter when filtering, otherwise makes one of them the drawing class.
+    fn select_classes(&mut self, classes: HashSet<L>, ctx: &Context) {
         if self.filter {
             self.shown_classes = self
                 .shown_classes
@@ -280,29 +1391,61 @@ impl Boundrs {
     }
 
     fn handle_left_right(&mut self, ctx: &Context) {
-        let next_pressed =
-            ctx.input().key_pressed(egui::Key::ArrowRight) | ctx.input().key_pressed(egui::Key::D);
-        let previous_pressed =
-            ctx.input().key_pressed(egui::Key::ArrowLeft) | ctx.input().key_pressed(egui::Key::A);
+        let next_pressed = self.bindings.pressed(ctx, Action::Next);
+        let previous_pressed = self.bindings.pressed(ctx, Action::Previous);
+        if self.bindings.pressed(ctx, Action::NextUnreviewed) {
+            self.go(DatasetMovement::NextProposed, ctx);
+            return;
+        }
 
+        let shown_classes = self.shown_classes.clone();
         let movement = match (next_pressed, previous_pressed, self.filter) {
             (true, false, false) => DatasetMovement::Next,
             (false, true, false) => DatasetMovement::Previous,
-            (true, false, true) => DatasetMovement::NextContaining(&self.shown_classes),
-            (false, true, true) => DatasetMovement::PreviousContaining(&self.shown_classes),
+            (true, false, true) => DatasetMovement::NextContaining(&shown_classes),
+            (false, true, true) => DatasetMovement::PreviousContaining(&shown_classes),
             _ => return,
         };
+        self.go(movement, ctx);
+    }
+
+    fn go(&mut self, movement: DatasetMovement<L>, ctx: &Context) {
+        // changing an interpolated label reviews it, changing a disputed one adjudicates it
+        if self.dataset.current_label().unwrap() != self.current_label {
+            self.dataset
+                .update_current_state(|state| {
+                    state.flags.remove(&Flag::Interpolated);
+                    state.flags.remove(&Flag::Disputed);
+                })
+                .unwrap();
+        }
+        self.notice = None;
+        // unreviewed predictions stay for later
+        let (_, i, _) = self.dataset.get_progress();
+        // a proposals file that could not be read is left alone
+        if self.dataset.proposals(i).is_ok() {
+            let predicted = self.proposals.of_origin(Origin::Predicted);
+            self.dataset.save_proposals(i, &predicted).unwrap();
+        }
         self.dataset
             .go(movement, self.current_label.clone())
             .unwrap();
         self.current_label = self.dataset.current_label().unwrap();
+        self.proposals.clear();
+        let (_, i, _) = self.dataset.get_progress();
+        match self.dataset.proposals(i) {
+            Ok(predicted) => self.proposals.set(Origin::Predicted, predicted),
+            Err(e) => self.notice = Some(format!("{e:#}")),
+        }
+        self.hovered_proposal = None;
         self.update_texture(ctx);
         self.update_mask(ctx);
     }
 }
 
-impl eframe::App for Boundrs {
+impl<L: Label> eframe::App for Boundrs<L> {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let mut goto = None;
         egui::Window::new("Boundrs Labeling").show(ctx, |ui| {
             let filename = self.dataset.current_name();
             ui.horizontal(|ui| {
@@ -312,21 +1455,63 @@ impl eframe::App for Boundrs {
             ui.horizontal(|ui| {
                 ui.label("Progress");
                 let (_, current, max) = self.dataset.get_progress();
-                ui.add(
-                    ProgressBar::new(current as f32 / max as f32)
-                        .show_percentage()
-                        .text(format!("{current} out of {max} images")),
-                );
+                goto = goto.or(widgets::seek_bar(ui, current, max));
             });
+            goto = goto.or(widgets::search_box(
+                ui,
+                &mut self.search_query,
+                &self.dataset,
+            ));
             ui.horizontal(|ui| {
                 ui.label("Filter opacity");
                 ui.add(Slider::new(&mut self.filter_opacity, 0..=255));
             });
             ui.horizontal(|ui| {
                 ui.label("Shown classes:");
-                ui.label(format!("{:?}", self.shown_classes));
+                let mut shown: Vec<_> = self.shown_classes.iter().collect();
+                shown.sort_by_key(|class| class.to_usize());
+                let names: Vec<_> = shown.into_iter().map(|class| class.to_name()).collect();
+                ui.label(names.join(", "));
             });
+            let pending = self.key_sequence.pending();
+            if !pending.is_empty() {
+                ui.horizontal(|ui| {
+                    ui.label("Pending keys:");
+                    ui.monospace(format!("{} …", widgets::shortcut_text(pending)));
+                });
+            }
+            ui.checkbox(&mut self.color_by_track, "Color boxes by track id");
+            if let Some(order) = self.dataset.order() {
+                ui.label(format!("Order: {order}"));
+            }
+            let flags = self.dataset.current_state().flags;
+            for flag in &flags {
+                ui.colored_label(Color32::YELLOW, format!("⚠ {}", flag.description()));
+            }
+            if flags.contains(&Flag::Interpolated) && ui.button("Mark as reviewed").clicked() {
+                self.dataset
+                    .update_current_state(|state| {
+                        state.flags.remove(&Flag::Interpolated);
+                    })
+                    .unwrap();
+            }
+            if flags.contains(&Flag::Disputed) && ui.button("Mark as adjudicated").clicked() {
+                self.dataset
+                    .update_current_state(|state| {
+                        state.flags.remove(&Flag::Disputed);
+                    })
+                    .unwrap();
+            }
+            if let Some(notice) = &self.notice {
+                ui.label(notice);
+            }
         });
+        if let Some(i) = goto {
+            self.go(DatasetMovement::Goto(i), ctx);
+        }
+        self.boxes_window(ctx);
+        self.proposals_window(ctx);
+        self.classes_window(ctx);
         egui::CentralPanel::default()
             .frame(egui::Frame::none().fill(Color32::BLACK))
             .show(ctx, |ui| {
@@ -358,6 +1543,11 @@ impl eframe::App for Boundrs {
                 // Handle clicks for bbs
                 self.handle_img_response(img_response, ui);
 
+                // Typing in the search box should not trigger shortcuts
+                if ctx.wants_keyboard_input() {
+                    return;
+                }
+
                 // Handle prev next picture keyboard
                 self.handle_left_right(ctx);
 
@@ -365,15 +1555,40 @@ impl eframe::App for Boundrs {
                 self.handle_class_keys(ctx);
 
                 // Handle filter mode
-                let filter_pressed = ctx.input().key_pressed(egui::Key::F);
+                let filter_pressed = self.bindings.pressed(ctx, Action::Filter);
                 if filter_pressed {
                     self.filter = !self.filter;
                 }
 
                 // Handle repeat button
-                if ctx.input().key_pressed(egui::Key::R) {
+                if self.bindings.pressed(ctx, Action::Repeat) {
                     self.repeat_bbs().unwrap();
                 }
+
+                // Handle propagated box proposals
+                self.handle_proposal_keys(ctx);
+
+                // Handle track id propagation
+                if self.bindings.pressed(ctx, Action::PropagateTracks) {
+                    self.propagate_tracks().unwrap();
+                }
+
+                // Handle keyframe interpolation
+                if self.bindings.pressed(ctx, Action::Interpolate) {
+                    let notice = self.interpolate().unwrap_or_else(|e| e.to_string());
+                    self.notice = Some(notice);
+                }
+
+                // Handle cheat sheet
+                if keymap::cheat_sheet_pressed(ctx) {
+                    self.show_cheat_sheet = !self.show_cheat_sheet;
+                }
             });
+        widgets::cheat_sheet(
+            ctx,
+            &mut self.show_cheat_sheet,
+            &self.bindings,
+            &self.shortcuts,
+        );
     }
 }
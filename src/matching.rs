@@ -0,0 +1,258 @@
+use clap::ValueEnum;
+use eframe::egui::{Rect, Vec2};
+
+use crate::dataset::{BoundingBox, Label, YoloBB};
+
+/// Intersection over union of two rects, 0 if they don't overlap.
+pub fn iou(a: Rect, b: Rect) -> f32 {
+    let intersection = a.intersect(b);
+    if intersection.width() <= 0.0 || intersection.height() <= 0.0 {
+        return 0.0;
+    }
+    let intersection = intersection.area();
+    intersection / (a.area() + b.area() - intersection)
+}
+
+/// Parses an IoU threshold, which has to be in (0, 1] for boxes that don't overlap to stay apart.
+pub fn parse_iou(iou: &str) -> std::result::Result<f32, String> {
+    match iou.trim().parse::<f32>() {
+        Ok(iou) if iou > 0.0 && iou <= 1.0 => Ok(iou),
+        _ => Err(format!("invalid IoU {iou:?}, expected a number in (0, 1]")),
+    }
+}
+
+/// How the boxes of two sets are paired up one-to-one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Assignment {
+    /// Repeatedly pair the two unpaired boxes with the highest IoU
+    Greedy,
+    /// Pair the boxes such that the sum of the IoUs is maximal
+    Hungarian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    pub a: usize,
+    pub b: usize,
+    pub iou: f32,
+}
+
+/// Pairs up boxes whose IoU is at least `threshold`, every box is in at most one pair.
+#[derive(Clone, Copy, Debug)]
+pub struct Matcher {
+    pub threshold: f32,
+    pub assignment: Assignment,
+}
+
+impl Matcher {
+    pub fn new(threshold: f32, assignment: Assignment) -> Self {
+        Matcher {
+            threshold,
+            assignment,
+        }
+    }
+
+    /// Matches of two labels, regardless of the classes of the boxes.
+    pub fn match_boxes<A: Label, B: Label>(&self, a: &[YoloBB<A>], b: &[YoloBB<B>]) -> Vec<Match> {
+        let a: Vec<_> = a.iter().map(relative_rect).collect();
+        let b: Vec<_> = b.iter().map(relative_rect).collect();
+        self.match_rects(&a, &b)
+    }
+
+    pub fn match_rects(&self, a: &[Rect], b: &[Rect]) -> Vec<Match> {
+        let ious: Vec<Vec<f32>> = a
+            .iter()
+            .map(|a| b.iter().map(|b| iou(*a, *b)).collect())
+            .collect();
+        let mut matches = match self.assignment {
+            Assignment::Greedy => greedy(&ious, self.threshold),
+            Assignment::Hungarian => hungarian(&ious, self.threshold),
+        };
+        matches.sort_by_key(|m| m.a);
+        matches
+    }
+}
+
+/// The rect of a box in an image of size 1x1, to compute IoUs with.
+pub fn relative_rect<L: Label>(bb: &YoloBB<L>) -> Rect {
+    // IoU does not depend on the image size, so the relative rects are good enough
+    bb.rect(Vec2::splat(1.0))
+}
+
+fn greedy(ious: &[Vec<f32>], threshold: f32) -> Vec<Match> {
+    let mut candidates: Vec<Match> = ious
+        .iter()
+        .enumerate()
+        .flat_map(|(a, row)| {
+            row.iter()
+                .enumerate()
+                .map(move |(b, iou)| Match { a, b, iou: *iou })
+        })
+        .filter(|m| m.iou >= threshold)
+        .collect();
+    candidates.sort_by(|m1, m2| m2.iou.total_cmp(&m1.iou));
+
+    let n_b = ious.first().map_or(0, Vec::len);
+    let (mut a_taken, mut b_taken) = (vec![false; ious.len()], vec![false; n_b]);
+    let mut matches = vec![];
+    for m in candidates {
+        if !a_taken[m.a] && !b_taken[m.b] {
+            a_taken[m.a] = true;
+            b_taken[m.b] = true;
+            matches.push(m);
+        }
+    }
+    matches
+}
+
+/// Maximum weight assignment with the Hungarian algorithm on the square cost matrix `1 - iou`,
+/// where pairs below the threshold can't do better than staying unmatched.
+fn hungarian(ious: &[Vec<f32>], threshold: f32) -> Vec<Match> {
+    let n_a = ious.len();
+    let n_b = ious.first().map_or(0, Vec::len);
+    let n = n_a.max(n_b);
+    if n == 0 {
+        return vec![];
+    }
+    let cost = |a: usize, b: usize| -> f64 {
+        match ious.get(a).and_then(|row| row.get(b)) {
+            Some(iou) if *iou >= threshold => 1.0 - *iou as f64,
+            _ => 1.0,
+        }
+    };
+
+    // potentials and matching with 1-based indices, p[b] is the row matched to column b
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; n + 1];
+    let mut p = vec![0; n + 1];
+    let mut way = vec![0; n + 1];
+    for a in 1..=n {
+        p[0] = a;
+        let mut b0 = 0;
+        let mut min_v = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[b0] = true;
+            let a0 = p[b0];
+            let mut delta = f64::INFINITY;
+            let mut b1 = 0;
+            for b in 1..=n {
+                if used[b] {
+                    continue;
+                }
+                let reduced = cost(a0 - 1, b - 1) - u[a0] - v[b];
+                if reduced < min_v[b] {
+                    min_v[b] = reduced;
+                    way[b] = b0;
+                }
+                if min_v[b] < delta {
+                    delta = min_v[b];
+                    b1 = b;
+                }
+            }
+            for b in 0..=n {
+                if used[b] {
+                    u[p[b]] += delta;
+                    v[b] -= delta;
+                } else {
+                    min_v[b] -= delta;
+                }
+            }
+            b0 = b1;
+            if p[b0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let b1 = way[b0];
+            p[b0] = p[b1];
+            b0 = b1;
+            if b0 == 0 {
+                break;
+            }
+        }
+    }
+
+    (1..=n)
+        .filter(|b| p[*b] != 0 && p[*b] <= n_a && *b <= n_b)
+        .map(|b| Match {
+            a: p[b] - 1,
+            b: b - 1,
+            iou: ious[p[b] - 1][b - 1],
+        })
+        .filter(|m| m.iou >= threshold)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(matches: &[Match]) -> Vec<(usize, usize)> {
+        let mut pairs: Vec<_> = matches.iter().map(|m| (m.a, m.b)).collect();
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn more_rows_than_columns() {
+        let ious = vec![vec![0.1, 0.7], vec![0.9, 0.2], vec![0.6, 0.8]];
+        assert_eq!(pairs(&hungarian(&ious, 0.5)), vec![(1, 0), (2, 1)]);
+        assert_eq!(pairs(&greedy(&ious, 0.5)), vec![(1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn more_columns_than_rows() {
+        let ious = vec![vec![0.0, 0.6, 0.9], vec![0.7, 0.0, 0.8]];
+        assert_eq!(pairs(&hungarian(&ious, 0.5)), vec![(0, 2), (1, 0)]);
+        assert_eq!(pairs(&greedy(&ious, 0.5)), vec![(0, 2), (1, 0)]);
+    }
+
+    #[test]
+    fn nothing_above_the_threshold() {
+        let ious = vec![vec![0.3, 0.49], vec![0.2, 0.0]];
+        assert!(hungarian(&ious, 0.5).is_empty());
+        assert!(greedy(&ious, 0.5).is_empty());
+        assert!(hungarian(&[], 0.5).is_empty());
+        assert!(greedy(&[vec![]], 0.5).is_empty());
+    }
+
+    #[test]
+    fn greedy_takes_the_best_pair_hungarian_the_best_sum() {
+        // greedy pairs 0 with 0 and leaves 1 alone, pairing crosswise sums to more
+        let ious = vec![vec![0.9, 0.8], vec![0.85, 0.0]];
+        assert_eq!(pairs(&greedy(&ious, 0.5)), vec![(0, 0)]);
+        let matches = hungarian(&ious, 0.5);
+        assert_eq!(pairs(&matches), vec![(0, 1), (1, 0)]);
+        let sum: f32 = matches.iter().map(|m| m.iou).sum();
+        assert!((sum - 1.65).abs() < 1e-6);
+    }
+
+    #[test]
+    fn hungarian_leaves_pairs_below_the_threshold_unmatched() {
+        // the best sum would use the 0.3 pair, which is below the threshold
+        let ious = vec![vec![0.9, 0.3], vec![0.6, 0.0]];
+        assert_eq!(pairs(&hungarian(&ious, 0.5)), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn match_rects_uses_the_iou_of_the_rects() {
+        let a = [Rect::from_min_size([0.0, 0.0].into(), [10.0, 10.0].into())];
+        let b = [
+            Rect::from_min_size([20.0, 20.0].into(), [10.0, 10.0].into()),
+            Rect::from_min_size([0.0, 5.0].into(), [10.0, 10.0].into()),
+        ];
+        let matches = Matcher::new(0.3, Assignment::Hungarian).match_rects(&a, &b);
+        assert_eq!(pairs(&matches), vec![(0, 1)]);
+        assert!((matches[0].iou - 1.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn iou_thresholds() {
+        assert_eq!(parse_iou("0.5"), Ok(0.5));
+        assert_eq!(parse_iou("1"), Ok(1.0));
+        assert!(parse_iou("0").is_err());
+        assert!(parse_iou("1.5").is_err());
+        assert!(parse_iou("x").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use eframe::egui::{Rect, Vec2};
+
+use crate::dataset::{BoundingBox, Dataset, Label, YoloBB, YoloLabel};
+use crate::matching::{relative_rect, Assignment, Matcher};
+use crate::project::Flag;
+
+/// How the coordinates of the boxes of a cluster are combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Coordinates {
+    /// Mean of the centers and sizes
+    Mean,
+    /// Median of the centers and sizes, robust against a single sloppy box
+    Median,
+}
+
+impl Coordinates {
+    fn combine(self, mut values: Vec<f32>) -> f32 {
+        match self {
+            Coordinates::Mean => values.iter().sum::<f32>() / values.len() as f32,
+            Coordinates::Median => {
+                values.sort_by(f32::total_cmp);
+                // the middle value, or the mean of the two in the middle
+                let n = values.len();
+                (values[(n - 1) / 2] + values[n / 2]) / 2.0
+            }
+        }
+    }
+}
+
+/// Boxes of different annotators that are the same object, at most one per annotator.
+struct Cluster<L: Label> {
+    boxes: Vec<YoloBB<L>>,
+}
+
+impl<L: Label> Cluster<L> {
+    fn rects(&self) -> Vec<Rect> {
+        self.boxes.iter().map(relative_rect).collect()
+    }
+    fn combined_rect(&self, coordinates: Coordinates) -> Rect {
+        let rects = self.rects();
+        let combine =
+            |value: fn(&Rect) -> f32| coordinates.combine(rects.iter().map(value).collect());
+        Rect::from_center_size(
+            [combine(|r| r.center().x), combine(|r| r.center().y)].into(),
+            [combine(Rect::width), combine(Rect::height)].into(),
+        )
+    }
+    /// Class with the most votes, on a tie the one of the first annotator.
+    fn class(&self) -> L {
+        let votes = |class: L| self.boxes.iter().filter(|bb| bb.class() == class).count();
+        let mut class = self.boxes[0].class();
+        for bb in &self.boxes[1..] {
+            if votes(bb.class()) > votes(class) {
+                class = bb.class();
+            }
+        }
+        class
+    }
+    fn consensus(&self, coordinates: Coordinates) -> YoloBB<L> {
+        let rect = self.combined_rect(coordinates);
+        YoloBB::from_rect(rect, Vec2::splat(1.0), self.class())
+    }
+    /// Every annotator drew the box with the same class.
+    fn is_unanimous(&self, annotators: usize) -> bool {
+        let class = self.boxes[0].class();
+        self.boxes.len() == annotators && self.boxes.iter().all(|bb| bb.class() == class)
+    }
+}
+
+/// Clusters the boxes of the labels, adding the boxes of one annotator after the other to the
+/// cluster they overlap most with.
+fn cluster<L: Label>(labels: &[YoloLabel<L>], matcher: &Matcher) -> Vec<Cluster<L>> {
+    let mut clusters: Vec<Cluster<L>> = vec![];
+    for label in labels {
+        let centers: Vec<_> = clusters
+            .iter()
+            .map(|c| c.combined_rect(Coordinates::Mean))
+            .collect();
+        let rects: Vec<_> = label.iter().map(relative_rect).collect();
+        let mut matched = vec![false; label.len()];
+        for m in matcher.match_rects(&centers, &rects) {
+            clusters[m.a].boxes.push(label[m.b]);
+            matched[m.b] = true;
+        }
+        for (bb, _) in label.iter().zip(matched).filter(|(_, matched)| !matched) {
+            clusters.push(Cluster { boxes: vec![*bb] });
+        }
+    }
+    clusters
+}
+
+/// Clusters the labels of an image, the clusters with at least `min_votes` boxes, by default more
+/// than half of the labels, make up the consensus label.
+fn merge_image<L: Label>(
+    labels: &[YoloLabel<L>],
+    matcher: &Matcher,
+    coordinates: Coordinates,
+    min_votes: Option<usize>,
+) -> (YoloLabel<L>, Vec<Cluster<L>>) {
+    let clusters = cluster(labels, matcher);
+    let votes = min_votes.unwrap_or(labels.len() / 2 + 1);
+    let label = clusters
+        .iter()
+        .filter(|c| c.boxes.len() >= votes)
+        .map(|c| c.consensus(coordinates))
+        .collect();
+    (label, clusters)
+}
+
+/// Merges the labels of the `annotators` into consensus labels in the labels directory
+/// of `out`. Boxes of the annotators overlapping by at least `iou` are clustered, the clusters
+/// with at least `min_votes` boxes, by default more than half of the annotators that labeled the
+/// image, become a box with the class most voted for and the combined coordinates.
+///
+/// Images with more than `max_disagreement` of their clusters not unanimous are flagged as
+/// disputed. Boxes of classes unknown to the label set are left out.
+pub fn merge<L: Label>(
+    out: &mut Dataset<L>,
+    annotators: &[Dataset<L>],
+    iou: f32,
+    coordinates: Coordinates,
+    min_votes: Option<usize>,
+    max_disagreement: f32,
+    overwrite: bool,
+) -> Result<()> {
+    if annotators.len() < 2 {
+        bail!("merging needs at least two label directories");
+    }
+    let images: Vec<usize> = (0..out.len())
+        .filter(|i| annotators.iter().any(|a| a.has_label(*i)))
+        .collect();
+    let existing = images.iter().filter(|i| out.has_label(**i)).count();
+    if existing > 0 && !overwrite {
+        bail!(
+            "{existing} of the merged images already have a label in {:?}, pass --overwrite to replace them",
+            out.label_path(0).parent().unwrap()
+        );
+    }
+
+    let matcher = Matcher::new(iou, Assignment::Hungarian);
+    let classes = L::all().len();
+    let (mut boxes, mut disputed, mut agreed) = (0, vec![], vec![]);
+    for i in &images {
+        let mut labels = vec![];
+        for annotator in annotators.iter().filter(|a| a.has_label(*i)) {
+            let mut label = annotator.label(*i)?;
+            label.retain(|bb| bb.class_id() < classes);
+            labels.push(label);
+        }
+        let (label, clusters) = merge_image(&labels, &matcher, coordinates, min_votes);
+        boxes += label.len();
+        out.save_label_at(*i, label)?;
+
+        let contested = clusters
+            .iter()
+            .filter(|c| !c.is_unanimous(labels.len()))
+            .count();
+        if contested as f32 > max_disagreement * clusters.len() as f32 {
+            disputed.push(*i);
+        } else {
+            agreed.push(*i);
+        }
+    }
+    out.update_states(&disputed, |state| {
+        state.flags.insert(Flag::Disputed);
+    })?;
+    out.update_states(&agreed, |state| {
+        state.flags.remove(&Flag::Disputed);
+    })?;
+
+    let names: Vec<_> = annotators
+        .iter()
+        .map(|a| format!("{:?}", a.label_path(0).parent().unwrap()))
+        .collect();
+    println!(
+        "Merged {} into {boxes} boxes of {} images, {} disputed",
+        names.join(", "),
+        images.len(),
+        disputed.len()
+    );
+    for i in &disputed {
+        println!(
+            "Disputed {}",
+            out.image_path(*i).file_name().unwrap().to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Card;
+
+    fn label(lines: &[&str]) -> YoloLabel<Card> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn cluster(lines: &[&str]) -> Cluster<Card> {
+        Cluster {
+            boxes: label(lines),
+        }
+    }
+
+    #[test]
+    fn class_tie_goes_to_the_first_annotator() {
+        let class = |lines: &[&str]| cluster(lines).class();
+        assert_eq!(class(&["1 0.5 0.5 0.2 0.2", "0 0.5 0.5 0.2 0.2"]), Card::K);
+        assert_eq!(class(&["0 0.5 0.5 0.2 0.2", "1 0.5 0.5 0.2 0.2"]), Card::A);
+        assert_eq!(
+            class(&[
+                "1 0.5 0.5 0.2 0.2",
+                "0 0.5 0.5 0.2 0.2",
+                "0 0.5 0.5 0.2 0.2"
+            ]),
+            Card::A
+        );
+        assert_eq!(
+            class(&[
+                "2 0.5 0.5 0.2 0.2",
+                "1 0.5 0.5 0.2 0.2",
+                "0 0.5 0.5 0.2 0.2",
+                "1 0.5 0.5 0.2 0.2"
+            ]),
+            Card::K
+        );
+    }
+
+    #[test]
+    fn median_of_the_coordinates() {
+        assert_eq!(Coordinates::Median.combine(vec![0.3, 0.1, 0.2]), 0.2);
+        assert_eq!(Coordinates::Median.combine(vec![0.4, 0.1, 0.2, 0.3]), 0.25);
+        assert_eq!(Coordinates::Mean.combine(vec![0.1, 0.2, 0.6]), 0.3);
+
+        // the sloppy third box moves the mean but not the median
+        let sloppy = cluster(&[
+            "0 0.5 0.5 0.2 0.2",
+            "0 0.5 0.5 0.2 0.2",
+            "0 0.56 0.5 0.2 0.2",
+        ]);
+        let median = sloppy.consensus(Coordinates::Median);
+        assert!((median.rect(Vec2::splat(1.0)).center().x - 0.5).abs() < 1e-5);
+        let mean = sloppy.consensus(Coordinates::Mean);
+        assert!((mean.rect(Vec2::splat(1.0)).center().x - 0.52).abs() < 1e-5);
+    }
+
+    #[test]
+    fn more_than_half_of_the_annotators_by_default() {
+        let matcher = Matcher::new(0.5, Assignment::Hungarian);
+        let boxes = |labels: &[YoloLabel<Card>], min_votes| {
+            merge_image(labels, &matcher, Coordinates::Median, min_votes)
+                .0
+                .len()
+        };
+        let both = "0 0.2 0.2 0.1 0.1";
+        let one = "1 0.7 0.7 0.1 0.1";
+        let two = [label(&[both, one]), label(&[both])];
+        assert_eq!(boxes(&two, None), 1);
+        assert_eq!(boxes(&two, Some(1)), 2);
+
+        let three = [label(&[both, one]), label(&[both]), label(&[])];
+        assert_eq!(boxes(&three, None), 1);
+        assert_eq!(boxes(&three, Some(3)), 0);
+        let four = [label(&[both]), label(&[both]), label(&[]), label(&[])];
+        assert_eq!(boxes(&four, None), 0);
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::dataset::{Dataset, Label};
+use crate::imagehash;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OrderKind {
+    /// Alphanumeric by file name
+    Name,
+    /// Shuffled with `--seed`
+    Random,
+    /// Most uncertain first, from the scores in `--scores`
+    Uncertainty,
+    /// Most boxes first
+    Boxes,
+    /// Oldest file first
+    Mtime,
+    /// Each image as different as possible from the ones before, by image hashes
+    Diversity,
+}
+
+/// The order in which the images of a dataset are labeled.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Order {
+    Name,
+    Random { seed: u64 },
+    Uncertainty { scores: PathBuf },
+    Boxes,
+    Mtime,
+    Diversity,
+}
+
+impl Order {
+    pub fn new(kind: OrderKind, seed: u64, scores: Option<PathBuf>) -> Result<Self> {
+        Ok(match kind {
+            OrderKind::Name => Order::Name,
+            OrderKind::Random => Order::Random { seed },
+            OrderKind::Uncertainty => match scores {
+                Some(scores) => Order::Uncertainty { scores },
+                None => bail!("ordering by uncertainty needs a file with --scores"),
+            },
+            OrderKind::Boxes => Order::Boxes,
+            OrderKind::Mtime => Order::Mtime,
+            OrderKind::Diversity => Order::Diversity,
+        })
+    }
+
+    /// Indices of the images of the dataset in this order.
+    pub fn sort<L: Label>(&self, dataset: &Dataset<L>) -> Result<Vec<usize>> {
+        let mut indices: Vec<usize> = (0..dataset.len()).collect();
+        match self {
+            Order::Name => {}
+            Order::Random { seed } => indices.shuffle(&mut StdRng::seed_from_u64(*seed)),
+            Order::Uncertainty { scores } => {
+                let scores = read_scores(scores)?;
+                let score = |i: usize| {
+                    let path = dataset.image_path(i);
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    let stem = path.file_stem().unwrap().to_string_lossy();
+                    scores
+                        .get(name.as_ref())
+                        .or_else(|| scores.get(stem.as_ref()))
+                        .copied()
+                };
+                // images without a score last
+                indices.sort_by(|a, b| {
+                    let (a, b) = (
+                        score(*a).unwrap_or(f32::NEG_INFINITY),
+                        score(*b).unwrap_or(f32::NEG_INFINITY),
+                    );
+                    b.total_cmp(&a)
+                });
+            }
+            Order::Boxes => {
+                let boxes = (0..dataset.len())
+                    .map(|i| Ok(dataset.label(i)?.len()))
+                    .collect::<Result<Vec<_>>>()?;
+                indices.sort_by_key(|i| std::cmp::Reverse(boxes[*i]));
+            }
+            Order::Mtime => {
+                let mtimes = (0..dataset.len())
+                    .map(|i| Ok(std::fs::metadata(dataset.image_path(i))?.modified()?))
+                    .collect::<Result<Vec<_>>>()?;
+                indices.sort_by_key(|i| mtimes[*i]);
+            }
+            Order::Diversity => {
+                let hashes = (0..dataset.len())
+                    .map(|i| {
+                        let path = dataset.image_path(i);
+                        imagehash::dhash(path).with_context(|| format!("could not hash {path:?}"))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                indices = farthest_first(&hashes);
+            }
+        }
+        Ok(indices)
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Order::Name => write!(f, "by name"),
+            Order::Random { seed } => write!(f, "random with seed {seed}"),
+            Order::Uncertainty { scores } => write!(f, "by uncertainty from {scores:?}"),
+            Order::Boxes => write!(f, "most boxes first"),
+            Order::Mtime => write!(f, "oldest first"),
+            Order::Diversity => write!(f, "most diverse first"),
+        }
+    }
+}
+
+/// Reads lines of an image name or file stem and its uncertainty, separated by a comma or spaces.
+fn read_scores(path: &Path) -> Result<HashMap<String, f32>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("could not read scores {path:?}"))?;
+    let mut scores = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<_> = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .collect();
+        let [name, score] = parts[..] else {
+            bail!("{path:?} line {}: expected a name and a score", i + 1);
+        };
+        // a header line is not a score
+        let Ok(score) = score.parse() else {
+            if i == 0 {
+                continue;
+            }
+            bail!("{path:?} line {}: invalid score {score:?}", i + 1);
+        };
+        scores.insert(name.to_string(), score);
+    }
+    Ok(scores)
+}
+
+/// Greedy farthest point order: starts with the first image and always continues with the image
+/// whose closest already ordered image is farthest away.
+fn farthest_first(hashes: &[u64]) -> Vec<usize> {
+    let mut order = vec![];
+    let mut ordered = vec![false; hashes.len()];
+    let mut closest = vec![u32::MAX; hashes.len()];
+    let mut next = (!hashes.is_empty()).then_some(0);
+    while let Some(i) = next {
+        order.push(i);
+        ordered[i] = true;
+        for (j, hash) in hashes.iter().enumerate() {
+            closest[j] = closest[j].min(imagehash::distance(hashes[i], *hash));
+        }
+        next = (0..hashes.len())
+            .filter(|j| !ordered[*j])
+            .max_by_key(|j| (closest[*j], std::cmp::Reverse(*j)));
+    }
+    order
+}
@@ -0,0 +1,187 @@
+use anyhow::{anyhow, bail, Context, Result};
+use eframe::egui::Rect;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::dataset::{BoundingBox, Dataset, Label, YoloBB};
+use crate::proposals::{Origin, Proposal};
+
+/// One detection of a COCO results file, the box is `[left, top, width, height]` in pixels.
+#[derive(Deserialize)]
+struct CocoResult {
+    image_id: CocoId,
+    category_id: usize,
+    bbox: [f32; 4],
+    score: f32,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+enum CocoId {
+    Number(u64),
+    Name(String),
+}
+
+#[derive(Deserialize)]
+struct CocoImage {
+    id: CocoId,
+    file_name: String,
+}
+
+#[derive(Deserialize)]
+struct CocoCategory {
+    id: usize,
+    name: String,
+}
+
+/// The parts of a COCO annotations file needed to resolve the ids of a results file.
+#[derive(Deserialize)]
+struct CocoAnnotations {
+    images: Vec<CocoImage>,
+    #[serde(default)]
+    categories: Vec<CocoCategory>,
+}
+
+/// File stem of a path, the name predictions are matched to images by.
+fn stem(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+/// Reads a directory with a Yolo prediction file per image, with the confidence as sixth column.
+fn read_yolo_dir<L: Label>(dir: &Path) -> Result<HashMap<String, Vec<Proposal<L>>>> {
+    let mut predictions = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "txt") {
+            continue;
+        }
+        let proposals = std::fs::read_to_string(&path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.parse())
+            .collect::<Result<Vec<Proposal<L>>>>()
+            .with_context(|| format!("in {path:?}"))?;
+        predictions.insert(stem(&path), proposals);
+    }
+    Ok(predictions)
+}
+
+/// Reads a COCO results file. Without annotations the image ids are the image file stems and
+/// the category ids the class ids, otherwise both are looked up in the annotations.
+fn read_coco<L: Label>(
+    dataset: &Dataset<L>,
+    results: &Path,
+    annotations: Option<&Path>,
+) -> Result<HashMap<String, Vec<Proposal<L>>>> {
+    let content = std::fs::read_to_string(results)?;
+    let results: Vec<CocoResult> =
+        serde_json::from_str(&content).with_context(|| format!("invalid results {results:?}"))?;
+
+    let (image_names, classes) = match annotations {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)?;
+            let annotations: CocoAnnotations = serde_json::from_str(&content)
+                .with_context(|| format!("invalid annotations {path:?}"))?;
+            let images: HashMap<_, _> = annotations
+                .images
+                .into_iter()
+                .map(|image| (image.id, stem(Path::new(&image.file_name))))
+                .collect();
+            let classes = annotations
+                .categories
+                .iter()
+                .map(|category| {
+                    let class = L::from_name(&category.name)
+                        .ok_or_else(|| anyhow!("unknown category {:?}", category.name))?;
+                    Ok((category.id, class.to_usize()))
+                })
+                .collect::<Result<HashMap<_, _>>>()?;
+            (Some(images), Some(classes))
+        }
+        None => (None, None),
+    };
+
+    let indices: HashMap<_, _> = (0..dataset.len())
+        .map(|i| (stem(dataset.image_path(i)), i))
+        .collect();
+    let mut predictions: HashMap<String, Vec<Proposal<L>>> = HashMap::new();
+    for result in results {
+        let name = match (&image_names, &result.image_id) {
+            (Some(names), id) => names
+                .get(id)
+                .cloned()
+                .ok_or_else(|| anyhow!("image id not in the annotations"))?,
+            (None, CocoId::Number(id)) => id.to_string(),
+            (None, CocoId::Name(name)) => name.clone(),
+        };
+        let class_id = match &classes {
+            Some(classes) => *classes
+                .get(&result.category_id)
+                .ok_or_else(|| anyhow!("unknown category id {}", result.category_id))?,
+            None => result.category_id,
+        };
+        // predictions for images that are not in the dataset are ignored by the caller
+        let Some(i) = indices.get(&name) else {
+            predictions.entry(name).or_default();
+            continue;
+        };
+        if class_id >= L::all().len() {
+            bail!("class id {class_id} is not a class of the label set");
+        }
+        let [left, top, width, height] = result.bbox;
+        let rect = Rect::from_min_size([left, top].into(), [width, height].into());
+        let bb = YoloBB::from_rect(rect, dataset.image_size(*i)?, L::from_usize(class_id));
+        predictions.entry(name).or_default().push(Proposal {
+            bb,
+            confidence: result.score,
+            origin: Origin::Predicted,
+        });
+    }
+    Ok(predictions)
+}
+
+/// Reads the predictions of another tool by image file stem. `source` is a directory with Yolo
+/// files with a confidence column or a COCO results file.
+pub fn read<L: Label>(
+    dataset: &Dataset<L>,
+    source: &Path,
+    annotations: Option<&Path>,
+) -> Result<HashMap<String, Vec<Proposal<L>>>> {
+    if source.is_dir() {
+        read_yolo_dir::<L>(source)
+    } else {
+        read_coco(dataset, source, annotations)
+    }
+}
+
+/// Imports the predictions of another tool as proposals to review in the app.
+/// The proposals of an image replace any proposals it had before.
+pub fn import<L: Label>(
+    dataset: &Dataset<L>,
+    source: &Path,
+    annotations: Option<&Path>,
+) -> Result<()> {
+    let mut predictions = read(dataset, source, annotations)?;
+    let (mut images, mut boxes) = (0, 0);
+    for i in 0..dataset.len() {
+        let Some(mut proposals) = predictions.remove(&stem(dataset.image_path(i))) else {
+            continue;
+        };
+        proposals.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        images += 1;
+        boxes += proposals.len();
+        dataset.save_proposals(i, &proposals)?;
+    }
+    println!("Imported {boxes} proposals for {images} images");
+    if !predictions.is_empty() {
+        let mut unknown: Vec<_> = predictions.into_keys().collect();
+        unknown.sort();
+        println!(
+            "Ignored predictions for {} images that are not in the dataset: {}",
+            unknown.len(),
+            unknown.join(", ")
+        );
+    }
+    Ok(())
+}
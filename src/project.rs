@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::order::Order;
+
+/// File in the labels directory that holds the project state.
+pub const PROJECT_FILE: &str = "boundrs.json";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    /// Advanced past the image although some source boxes have no refined label
+    Incomplete,
+    /// The label was interpolated between keyframes and not reviewed yet
+    Interpolated,
+    /// The annotators of a merged label disagree and it needs adjudication
+    Disputed,
+}
+
+impl Flag {
+    pub fn description(self) -> &'static str {
+        match self {
+            Flag::Incomplete => "some boxes were not refined",
+            Flag::Interpolated => "interpolated, not reviewed yet",
+            Flag::Disputed => "annotators disagree, needs adjudication",
+        }
+    }
+}
+
+/// What is known about an image beyond its label file.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct ImageState {
+    /// Indices of the source boxes that are intentionally not refined
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub ignored: BTreeSet<usize>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub flags: BTreeSet<Flag>,
+}
+
+impl ImageState {
+    fn is_empty(&self) -> bool {
+        *self == ImageState::default()
+    }
+}
+
+/// Order of the images, saved so that random and diverse orders stay the same.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedOrder {
+    pub order: Order,
+    /// Image names in that order
+    pub images: Vec<String>,
+}
+
+/// State of a labeling project that does not fit into the Yolo label files, keyed by image name.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ProjectState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    order: Option<SavedOrder>,
+    #[serde(default)]
+    images: BTreeMap<String, ImageState>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl ProjectState {
+    /// Loads the state from the labels directory, a missing file is an empty state.
+    pub fn load(labels_dir: &Path) -> Result<Self> {
+        let path = labels_dir.join(PROJECT_FILE);
+        let mut state = if path.is_file() {
+            let content = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&content).with_context(|| format!("invalid project {path:?}"))?
+        } else {
+            ProjectState::default()
+        };
+        state.path = path;
+        Ok(state)
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    fn save(&self) -> Result<()> {
+        let file = std::fs::File::create(&self.path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn order(&self) -> Option<&SavedOrder> {
+        self.order.as_ref()
+    }
+    pub fn set_order(&mut self, order: SavedOrder) -> Result<()> {
+        self.order = Some(order);
+        self.save()
+    }
+
+    pub fn image(&self, name: &str) -> ImageState {
+        self.images.get(name).cloned().unwrap_or_default()
+    }
+    /// Changes the state of an image and saves the project if anything changed.
+    pub fn update_image(&mut self, name: &str, update: impl FnOnce(&mut ImageState)) -> Result<()> {
+        let mut image = self.image(name);
+        update(&mut image);
+        if image == self.image(name) {
+            return Ok(());
+        }
+        self.set_image(name, image);
+        self.save()
+    }
+    /// Applies the same change to many images, saving the project once.
+    pub fn update_images(
+        &mut self,
+        names: &[String],
+        mut update: impl FnMut(&mut ImageState),
+    ) -> Result<()> {
+        let mut changed = false;
+        for name in names {
+            let mut image = self.image(name);
+            update(&mut image);
+            if image != self.image(name) {
+                self.set_image(name, image);
+                changed = true;
+            }
+        }
+        if changed {
+            self.save()?;
+        }
+        Ok(())
+    }
+    /// Replaces the states of many images, saving the project once.
+    pub fn set_images(&mut self, images: Vec<(String, ImageState)>) -> Result<()> {
+        for (name, image) in images {
+            self.set_image(&name, image);
+        }
+        self.save()
+    }
+    fn set_image(&mut self, name: &str, image: ImageState) {
+        if image.is_empty() {
+            self.images.remove(name);
+        } else {
+            self.images.insert(name.to_string(), image);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use eframe::egui::{ColorImage, Rect, Vec2};
+
+use crate::dataset::{BoundingBox, Label, YoloBB, YoloLabel};
+use crate::proposals::{Origin, Proposal};
+
+/// Longest side of the template in samples, larger boxes are sampled sparsely.
+const TEMPLATE_SAMPLES: f32 = 48.0;
+
+/// Grayscale copy of an image for template matching.
+struct Gray {
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+}
+
+impl Gray {
+    fn new(image: &ColorImage) -> Self {
+        let pixels = image
+            .pixels
+            .iter()
+            .map(|p| 0.299 * p.r() as f32 + 0.587 * p.g() as f32 + 0.114 * p.b() as f32)
+            .collect();
+        Gray {
+            width: image.size[0],
+            height: image.size[1],
+            pixels,
+        }
+    }
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Pixels of the template, sampled every `stride` pixels from its top left corner.
+struct Template {
+    x: usize,
+    y: usize,
+    columns: usize,
+    rows: usize,
+    stride: usize,
+    // mean free values and their norm
+    values: Vec<f32>,
+    norm: f32,
+}
+
+impl Template {
+    fn new(image: &Gray, rect: Rect) -> Option<Self> {
+        let (x, y) = (rect.left().max(0.0) as usize, rect.top().max(0.0) as usize);
+        let right = (rect.right() as usize).min(image.width);
+        let bottom = (rect.bottom() as usize).min(image.height);
+        let (width, height) = (right.checked_sub(x)?, bottom.checked_sub(y)?);
+        if width < 4 || height < 4 {
+            return None;
+        }
+        let stride = (width.max(height) as f32 / TEMPLATE_SAMPLES)
+            .ceil()
+            .max(1.0) as usize;
+        let (columns, rows) = (width / stride, height / stride);
+        let mut values: Vec<f32> = (0..rows)
+            .flat_map(|r| (0..columns).map(move |c| (c, r)))
+            .map(|(c, r)| image.get(x + c * stride, y + r * stride))
+            .collect();
+        let norm = center(&mut values);
+        Some(Template {
+            x,
+            y,
+            columns,
+            rows,
+            stride,
+            values,
+            norm,
+        })
+    }
+
+    /// Normalized cross correlation with `image` when the template is moved by `(dx, dy)`.
+    fn correlation(
+        &self,
+        image: &Gray,
+        dx: isize,
+        dy: isize,
+        window: &mut Vec<f32>,
+    ) -> Option<f32> {
+        let x = self.x.checked_add_signed(dx)?;
+        let y = self.y.checked_add_signed(dy)?;
+        if x + (self.columns - 1) * self.stride >= image.width
+            || y + (self.rows - 1) * self.stride >= image.height
+        {
+            return None;
+        }
+        window.clear();
+        for r in 0..self.rows {
+            for c in 0..self.columns {
+                window.push(image.get(x + c * self.stride, y + r * self.stride));
+            }
+        }
+        let norm = center(window);
+        if self.norm == 0.0 || norm == 0.0 {
+            // flat patches don't say anything about the movement
+            return Some(0.0);
+        }
+        let dot: f32 = self
+            .values
+            .iter()
+            .zip(window.iter())
+            .map(|(a, b)| a * b)
+            .sum();
+        Some(dot / (self.norm * norm))
+    }
+}
+
+/// Subtracts the mean from `values` and returns their norm.
+fn center(values: &mut [f32]) -> f32 {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    values.iter_mut().for_each(|v| *v -= mean);
+    values.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+/// Finds the translation of `rect` from `previous` into `current` with the best correlation.
+///
+/// Searches coarsely in steps of half the template stride, then pixel by pixel around the best step.
+fn track(previous: &Gray, current: &Gray, rect: Rect) -> Option<(Vec2, f32)> {
+    let template = Template::new(previous, rect)?;
+    let radius = (0.25 * rect.width().max(rect.height())).max(8.0) as isize;
+    let mut window = Vec::with_capacity(template.values.len());
+    let mut best = (0, 0, template.correlation(current, 0, 0, &mut window)?);
+    let mut search =
+        |best: &mut (isize, isize, f32), (x0, y0): (isize, isize), radius: isize, step: usize| {
+            for dy in (-radius..=radius).step_by(step) {
+                for dx in (-radius..=radius).step_by(step) {
+                    let (dx, dy) = (x0 + dx, y0 + dy);
+                    match template.correlation(current, dx, dy, &mut window) {
+                        Some(score) if score > best.2 => *best = (dx, dy, score),
+                        _ => {}
+                    }
+                }
+            }
+        };
+    let step = (template.stride / 2).max(1);
+    search(&mut best, (0, 0), radius, step);
+    let (x0, y0) = (best.0, best.1);
+    search(&mut best, (x0, y0), step as isize, 1);
+    let (dx, dy, score) = best;
+    Some((Vec2::new(dx as f32, dy as f32), score.max(0.0)))
+}
+
+/// Tracks every box of `label` from the previous image into the current one.
+///
+/// Only translations are found, boxes that can't be tracked are proposed unchanged
+/// with a confidence of 0.
+pub fn propagate<L: Label>(
+    previous: &ColorImage,
+    current: &ColorImage,
+    label: &YoloLabel<L>,
+) -> Vec<Proposal<L>> {
+    let untracked = |bb: &YoloBB<L>| Proposal {
+        bb: *bb,
+        confidence: 0.0,
+        origin: Origin::Tracked,
+    };
+    if previous.size != current.size {
+        return label.iter().map(untracked).collect();
+    }
+    let size = Vec2::new(current.size[0] as f32, current.size[1] as f32);
+    let (previous, current) = (Gray::new(previous), Gray::new(current));
+    label
+        .iter()
+        .map(|bb| {
+            let rect = bb.rect(size);
+            match track(&previous, &current, rect) {
+                Some((offset, confidence)) => {
+                    let mut moved = YoloBB::from_rect(rect.translate(offset), size, bb.class());
+                    moved.set_track(bb.track());
+                    Proposal {
+                        bb: moved,
+                        confidence,
+                        origin: Origin::Tracked,
+                    }
+                }
+                None => untracked(bb),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,177 @@
+use anyhow::{bail, Result};
+use eframe::egui::{Align2, Color32, FontId, Painter, Rect, Shape, Stroke};
+use std::str::FromStr;
+
+use crate::dataset::{BoundingBox, Label, YoloBB};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// Tracked from the previous image, only kept while the image is shown
+    Tracked,
+    /// Predicted by a model, kept in the proposals file of the image until reviewed
+    Predicted,
+}
+
+/// A box suggested by the app, e.g. tracked from the previous image.
+#[derive(Clone, Copy, Debug)]
+pub struct Proposal<L: Label> {
+    pub bb: YoloBB<L>,
+    /// How sure the source of the proposal is, between 0 and 1
+    pub confidence: f32,
+    pub origin: Origin,
+}
+
+/// A Yolo label line with the confidence as sixth column, which defaults to 1 if missing.
+/// Boxes of classes unknown to the label set are rejected.
+impl<L: Label> FromStr for Proposal<L> {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<_> = s.split_whitespace().collect();
+        let confidence = match parts.len() {
+            5 => 1.0,
+            6 => parts[5].parse()?,
+            n => bail!("expected 5 or 6 columns, found {n} in {s:?}"),
+        };
+        let bb: YoloBB<L> = parts[..5].join(" ").parse()?;
+        if bb.class_id() >= L::all().len() {
+            bail!("unknown class id {} in {s:?}", bb.class_id());
+        }
+        Ok(Proposal {
+            bb,
+            confidence,
+            origin: Origin::Predicted,
+        })
+    }
+}
+
+impl<L: Label> Proposal<L> {
+    pub fn as_string(&self) -> String {
+        format!("{} {}", self.bb.as_string(), self.confidence)
+    }
+}
+
+/// Proposed boxes that only become part of the label once they are accepted.
+///
+/// Proposals below `min_confidence` are hidden. The most confident proposal has the focus,
+/// the accept and reject keys act on it.
+#[derive(Debug)]
+pub struct Proposals<L: Label> {
+    proposals: Vec<Proposal<L>>,
+    pub min_confidence: f32,
+}
+
+impl<L: Label> Proposals<L> {
+    pub fn new() -> Self {
+        Proposals {
+            proposals: vec![],
+            min_confidence: 0.0,
+        }
+    }
+
+    /// Replaces the proposals of the same origin, the most confident one gets the focus.
+    pub fn set(&mut self, origin: Origin, proposals: Vec<Proposal<L>>) {
+        self.proposals.retain(|proposal| proposal.origin != origin);
+        self.proposals.extend(proposals);
+        self.proposals
+            .sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    }
+    pub fn of_origin(&self, origin: Origin) -> Vec<Proposal<L>> {
+        self.iter()
+            .filter(|proposal| proposal.origin == origin)
+            .copied()
+            .collect()
+    }
+    pub fn clear(&mut self) {
+        self.proposals.clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.proposals.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Proposal<L>> {
+        self.proposals.iter()
+    }
+    /// Proposals that are not hidden, with their index.
+    pub fn visible(&self) -> impl Iterator<Item = (usize, &Proposal<L>)> {
+        self.proposals
+            .iter()
+            .enumerate()
+            .filter(|(_, proposal)| proposal.confidence >= self.min_confidence)
+    }
+    pub fn hidden(&self) -> usize {
+        self.proposals.len() - self.visible().count()
+    }
+    /// Index of the proposal the keys act on.
+    pub fn focused(&self) -> Option<usize> {
+        // sorted by confidence, so the first visible one is the first one
+        self.visible().next().map(|(i, _)| i)
+    }
+    pub fn set_class(&mut self, i: usize, class: L) {
+        if let Some(proposal) = self.proposals.get_mut(i) {
+            proposal.bb.set_class(class);
+        }
+    }
+
+    /// Removes the `i`-th proposal and returns its box for the label.
+    pub fn accept(&mut self, i: usize) -> Option<YoloBB<L>> {
+        (i < self.proposals.len()).then(|| self.proposals.remove(i).bb)
+    }
+    pub fn reject(&mut self, i: usize) {
+        if i < self.proposals.len() {
+            self.proposals.remove(i);
+        }
+    }
+    /// Removes the proposals that are not hidden and returns their boxes.
+    pub fn accept_visible(&mut self) -> Vec<YoloBB<L>> {
+        let min_confidence = self.min_confidence;
+        let (accepted, rest) = self
+            .proposals
+            .drain(..)
+            .partition(|proposal| proposal.confidence >= min_confidence);
+        self.proposals = rest;
+        accepted
+            .into_iter()
+            .map(|proposal: Proposal<L>| proposal.bb)
+            .collect()
+    }
+
+    /// Draws the proposals as dashed ghost boxes on the image shown at `image_rect`.
+    pub fn draw(&self, painter: &Painter, image_rect: Rect, highlighted: Option<usize>) {
+        let focused_proposal = self.focused();
+        for (i, proposal) in self.visible() {
+            let focused = Some(i) == focused_proposal || highlighted == Some(i);
+            let color = proposal.bb.class().color();
+            let alpha = if focused { 255 } else { 140 };
+            let color = Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), alpha);
+            let width = if focused { 4.0 } else { 2.0 };
+            let rect = proposal
+                .bb
+                .rect(image_rect.size())
+                .translate(image_rect.left_top().to_vec2());
+            let corners = [
+                rect.left_top(),
+                rect.right_top(),
+                rect.right_bottom(),
+                rect.left_bottom(),
+                rect.left_top(),
+            ];
+            painter.extend(Shape::dashed_line(
+                &corners,
+                Stroke::new(width, color),
+                10.0,
+                6.0,
+            ));
+            painter.text(
+                rect.left_top(),
+                Align2::LEFT_BOTTOM,
+                format!(
+                    "{} {:.0}%",
+                    proposal.bb.class().to_name(),
+                    proposal.confidence * 100.0
+                ),
+                FontId::monospace(20.0),
+                color,
+            );
+        }
+    }
+}
@@ -1,28 +1,49 @@
 use anyhow::Result;
 use eframe::egui;
 use egui::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::dataset::{
-    BoundingBox, Card, CardSuit, Dataset, DatasetMovement, Label, Suit, YoloLabel,
-};
+use crate::dataset::{BoundingBox, Dataset, DatasetMovement, Label, Refine, YoloLabel};
+use crate::keymap::{self, Action, Bindings};
+use crate::matching::Matcher;
+use crate::project::Flag;
+use crate::shortcuts::{KeySequence, SEQUENCE_TIMEOUT};
+use crate::widgets;
 // use image::{Rgba, RgbaImage};
 
-pub struct Relabeling {
+/// Refines the boxes of a dataset labeled with `S` into the finer classes of `T`, one box at a
+/// time by pressing the shortcut of the `S::Detail` that refines the highlighted box.
+pub struct Relabeling<S: Refine<T>, T: Label> {
     // index of currently editing label in old_label
     highlighted: Option<usize>,
     image_texture: egui::TextureHandle,
     image_rect: Rect,
-    old_dataset: Dataset<Card>,
-    new_dataset: Dataset<CardSuit>,
-    old_label: YoloLabel<Card>,
-    new_label: YoloLabel<CardSuit>,
+    old_dataset: Dataset<S>,
+    new_dataset: Dataset<T>,
+    old_label: YoloLabel<S>,
+    new_label: YoloLabel<T>,
+    search_query: String,
+    key_sequence: KeySequence,
+    bindings: Bindings,
+    shortcuts: HashMap<Vec<Key>, S::Detail>,
+    show_cheat_sheet: bool,
+    matcher: Matcher,
+    // why the last movement was refused
+    notice: Option<String>,
 }
 
-impl Relabeling {
-    pub fn build_app(cc: &eframe::CreationContext<'_>) -> Box<dyn eframe::App> {
-        let old_dataset = Dataset::from_input_dir().unwrap();
-        let new_dataset = Dataset::with_label_prefix("new_").unwrap();
+impl<S: Refine<T>, T: Label> Relabeling<S, T> {
+    pub fn build_app(
+        cc: &eframe::CreationContext<'_>,
+        bindings: Bindings,
+        shortcuts: HashMap<Vec<Key>, S::Detail>,
+        mut old_dataset: Dataset<S>,
+        new_dataset: Dataset<T>,
+        matcher: Matcher,
+    ) -> Box<dyn eframe::App> {
+        // continue with the first image that is not relabeled yet
+        let (_, start, _) = new_dataset.get_progress();
+        old_dataset.goto(start);
         let image = old_dataset.current_image().unwrap();
         let image_texture =
             cc.egui_ctx
@@ -30,7 +51,7 @@ impl Relabeling {
         let old_label = old_dataset.current_label().unwrap();
         let new_label = new_dataset.current_label().unwrap();
         let highlighted = None;
-        let mut relabeling = Relabeling {
+        let mut relabeling = Relabeling::<S, T> {
             highlighted,
             image_texture,
             image_rect: Rect::NOTHING,
@@ -38,6 +59,13 @@ impl Relabeling {
             new_dataset,
             old_label,
             new_label,
+            search_query: String::new(),
+            key_sequence: KeySequence::new(SEQUENCE_TIMEOUT),
+            bindings,
+            shortcuts,
+            show_cheat_sheet: false,
+            matcher,
+            notice: None,
         };
         relabeling.highlighted = relabeling.find_next_highlighted();
         Box::new(relabeling)
@@ -88,21 +116,32 @@ impl Relabeling {
             self.draw_label_text(painter, text_pos, bb.class());
         }
     }
+    /// Source boxes that neither have a refined box nor are skipped.
+    fn missing(&self) -> Vec<usize> {
+        let matches = self.matcher.match_boxes(&self.old_label, &self.new_label);
+        let ignored = self.new_dataset.current_state().ignored;
+        (0..self.old_label.len())
+            .filter(|i| matches.iter().all(|m| m.a != *i) && !ignored.contains(i))
+            .collect()
+    }
     fn find_next_highlighted(&self) -> Option<usize> {
-        let size = self.image_rect.size();
-        for (i, old_bbs) in self.old_label.iter().enumerate() {
-            if self.new_label.iter().all(|new_bbs| {
-                let old_rect = old_bbs.rect(size);
-                let new_rect = new_bbs.rect(size);
-                let iou = old_rect.intersect(new_rect).area() / old_rect.union(new_rect).area();
-                iou < 0.95
-            }) {
-                return Some(i);
-            }
-        }
-        None
+        self.missing().first().copied()
     }
     fn draw_highlight(&self, ui: &mut Ui) {
+        let size = self.image_rect.size();
+        for missing in self.missing() {
+            let bb = &self.old_label[missing];
+            let screen_rect = [
+                self.to_screen_coordinates(bb.rect(size).left_top()),
+                self.to_screen_coordinates(bb.rect(size).right_bottom()),
+            ]
+            .into();
+            ui.painter().rect_stroke(
+                screen_rect,
+                Rounding::none(),
+                Stroke::new(4.0, Color32::RED),
+            );
+        }
         if let Some(highlighted) = self.highlighted {
             let bb = &self.old_label[highlighted];
             let size = self.image_rect.size();
@@ -122,20 +161,44 @@ impl Relabeling {
         let image = self.old_dataset.current_image().unwrap();
         self.image_texture = ctx.load_texture("my-image", image, egui::TextureFilter::Linear);
     }
-    fn go(
+    fn go(&mut self, move_old: DatasetMovement<S>, move_new: DatasetMovement<T>, ctx: &Context) {
+        let missing = self.missing().len();
+        if move_old == DatasetMovement::Next && missing > 0 {
+            self.notice = Some(format!(
+                "{missing} boxes still need a refined label, skip them or advance anyway"
+            ));
+            return;
+        }
+        if missing == 0 {
+            self.new_dataset
+                .update_current_state(|state| {
+                    state.flags.remove(&Flag::Incomplete);
+                })
+                .unwrap();
+        }
+        self.move_datasets(move_old, move_new, ctx);
+    }
+    /// Goes to the next image even if boxes are missing, which is remembered with a flag.
+    fn advance_anyway(&mut self, ctx: &Context) {
+        if !self.missing().is_empty() {
+            self.new_dataset
+                .update_current_state(|state| {
+                    state.flags.insert(Flag::Incomplete);
+                })
+                .unwrap();
+        }
+        self.move_datasets(DatasetMovement::Next, DatasetMovement::Next, ctx);
+        if self.new_label.is_empty() {
+            self.repeat_bbs().unwrap();
+        }
+    }
+    fn move_datasets(
         &mut self,
-        move_old: DatasetMovement<Card>,
-        move_new: DatasetMovement<CardSuit>,
+        move_old: DatasetMovement<S>,
+        move_new: DatasetMovement<T>,
         ctx: &Context,
     ) {
-        if move_old == DatasetMovement::Next && self.new_label.len() != self.old_label.len() {
-            println!(
-                "Missing labels: len new {} vs len old {}",
-                self.new_label.len(),
-                self.old_label.len(),
-            );
-            return;
-        }
+        self.notice = None;
         self.old_dataset
             .go(move_old, self.old_label.clone())
             .unwrap();
@@ -148,8 +211,8 @@ impl Relabeling {
         self.update_texture(ctx);
     }
     fn handle_left_right(&mut self, ctx: &Context) {
-        let next_pressed = ctx.input().key_pressed(egui::Key::ArrowRight);
-        let previous_pressed = ctx.input().key_pressed(egui::Key::ArrowLeft);
+        let next_pressed = self.bindings.pressed(ctx, Action::Next);
+        let previous_pressed = self.bindings.pressed(ctx, Action::Previous);
 
         let (move_old, move_new) = match (next_pressed, previous_pressed) {
             (true, false) => (DatasetMovement::Next, DatasetMovement::Next),
@@ -163,54 +226,76 @@ impl Relabeling {
         }
     }
     fn handle_clear(&mut self, ctx: &Context) {
-        let delete_pressed = ctx.input().key_pressed(egui::Key::Delete);
+        let delete_pressed = self.bindings.pressed(ctx, Action::Clear);
         if delete_pressed {
             self.new_label = vec![];
+            self.new_dataset
+                .update_current_state(|state| state.ignored.clear())
+                .unwrap();
         }
         self.highlighted = self.find_next_highlighted();
     }
-    fn classes_pressed(&self, ctx: &Context) -> HashSet<Suit> {
-        let mut classes = HashSet::new();
-        for (keys, class) in Suit::shortcuts() {
-            if keys.iter().all(|key| ctx.input().key_pressed(*key)) {
-                classes.insert(class);
-            }
+    fn handle_skip(&mut self, ctx: &Context) {
+        if self.bindings.pressed(ctx, Action::Advance) {
+            self.advance_anyway(ctx);
         }
-        classes
+        if !self.bindings.pressed(ctx, Action::Skip) {
+            return;
+        }
+        if let Some(highlighted) = self.highlighted {
+            self.new_dataset
+                .update_current_state(|state| {
+                    state.ignored.insert(highlighted);
+                })
+                .unwrap();
+            self.highlighted = self.find_next_highlighted();
+            self.advance_if_done(ctx);
+        }
+    }
+    fn advance_if_done(&mut self, ctx: &Context) {
+        if self.missing().is_empty() {
+            let (move_old, move_new) = (DatasetMovement::Next, DatasetMovement::Next);
+            self.go(move_old, move_new, ctx);
+            self.repeat_bbs().unwrap();
+        }
+    }
+    fn classes_pressed(&mut self, ctx: &Context) -> HashSet<S::Detail> {
+        self.key_sequence
+            .update(ctx, &self.shortcuts)
+            .into_iter()
+            .collect()
     }
     fn handle_class_keys(&mut self, ctx: &Context) {
-        let suits = self.classes_pressed(ctx);
-        if let (Some(suit), Some(highlighted)) = (suits.into_iter().next(), self.highlighted) {
+        let details = self.classes_pressed(ctx);
+        if let (Some(detail), Some(highlighted)) = (details.into_iter().next(), self.highlighted) {
             let size = self.image_rect.size();
             let old_bbx = self.old_label[highlighted];
-            let card = old_bbx.class();
-            let new_class = CardSuit(card, suit);
+            let Some(new_class) = old_bbx.class().refine(detail) else {
+                self.notice = Some(format!(
+                    "{} can not be refined with {}",
+                    old_bbx.class().to_name(),
+                    detail.to_name()
+                ));
+                return;
+            };
             let new_bbs = BoundingBox::from_rect(old_bbx.rect(size), size, new_class);
             self.new_label.push(new_bbs);
             self.highlighted = self.find_next_highlighted();
-            if self.new_label.len() == self.old_label.len() && self.highlighted.is_none() {
-                let (move_old, move_new) = (DatasetMovement::Next, DatasetMovement::Next);
-                self.go(move_old, move_new, ctx);
-                self.repeat_bbs().unwrap();
-            }
+            self.advance_if_done(ctx);
         }
     }
-    fn take_similar_bbs(&mut self, new_label_candidate: YoloLabel<CardSuit>) {
+    fn take_similar_bbs(&mut self, new_label_candidate: YoloLabel<T>) {
         let size = self.image_rect.size();
-        self.new_label = vec![];
-        for old_bbs in self.old_label.iter() {
-            for new_bbs in new_label_candidate.iter() {
-                let old_rect = old_bbs.rect(size);
-                let new_rect = new_bbs.rect(size);
-                let intersect = old_rect.intersect(new_rect).area();
-                let union = old_rect.union(new_rect).area();
-                let iou = intersect / union;
-                if iou > 0.95 {
-                    let new_label = BoundingBox::from_rect(old_rect, size, new_bbs.class());
-                    self.new_label.push(new_label)
-                }
-            }
-        }
+        let matches = self
+            .matcher
+            .match_boxes(&self.old_label, &new_label_candidate);
+        self.new_label = matches
+            .into_iter()
+            .map(|m| {
+                let old_rect = self.old_label[m.a].rect(size);
+                BoundingBox::from_rect(old_rect, size, new_label_candidate[m.b].class())
+            })
+            .collect();
     }
     pub fn repeat_bbs(&mut self) -> Result<()> {
         let previous_label = self.new_dataset.previous_label()?;
@@ -220,8 +305,10 @@ impl Relabeling {
     }
 }
 
-impl eframe::App for Relabeling {
+impl<S: Refine<T>, T: Label> eframe::App for Relabeling<S, T> {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let mut goto = None;
+        let mut advance = false;
         egui::Window::new("Boundrs Labeling").show(ctx, |ui| {
             let filename = self.old_dataset.current_name();
             ui.horizontal(|ui| {
@@ -231,13 +318,79 @@ impl eframe::App for Relabeling {
             ui.horizontal(|ui| {
                 ui.label("Progress");
                 let (_, current, max) = self.old_dataset.get_progress();
-                ui.add(
-                    ProgressBar::new(current as f32 / max as f32)
-                        .show_percentage()
-                        .text(format!("{current} out of {max} images")),
-                );
+                goto = goto.or(widgets::seek_bar(ui, current, max));
             });
+            goto = goto.or(widgets::search_box(
+                ui,
+                &mut self.search_query,
+                &self.old_dataset,
+            ));
+            let missing = self.missing();
+            let state = self.new_dataset.current_state();
+            let refined = self
+                .matcher
+                .match_boxes(&self.old_label, &self.new_label)
+                .len();
+            ui.label(format!(
+                "Refined {refined} of {} boxes, {} skipped",
+                self.old_label.len(),
+                state.ignored.len(),
+            ));
+            if !missing.is_empty() {
+                let names: Vec<_> = missing
+                    .iter()
+                    .map(|i| self.old_label[*i].class().to_name())
+                    .collect();
+                ui.horizontal(|ui| {
+                    ui.label("Missing:");
+                    ui.colored_label(Color32::RED, names.join(", "));
+                });
+                let advance_keys: Vec<_> = self
+                    .bindings
+                    .keys(Action::Advance)
+                    .iter()
+                    .map(|key| widgets::shortcut_text(&[*key]))
+                    .collect();
+                if ui
+                    .button(format!("Advance anyway ({})", advance_keys.join(", ")))
+                    .clicked()
+                {
+                    advance = true;
+                }
+            }
+            for flag in state.flags {
+                ui.colored_label(Color32::YELLOW, format!("⚠ {}", flag.description()));
+            }
+            if let Some(notice) = &self.notice {
+                ui.colored_label(Color32::RED, notice);
+            }
+            if let Some(highlighted) = self.highlighted {
+                let class = self.old_label[highlighted].class();
+                ui.label(format!("Refine {} into:", class.to_name()));
+                let detail_keys: HashMap<S::Detail, &Vec<Key>> = self
+                    .shortcuts
+                    .iter()
+                    .map(|(keys, detail)| (*detail, keys))
+                    .collect();
+                egui::Grid::new("refinements").show(ui, |ui| {
+                    for (detail, refined) in class.refinements() {
+                        widgets::color_swatch(ui, refined.color());
+                        ui.label(refined.to_name());
+                        let keys = detail_keys
+                            .get(&detail)
+                            .map(|keys| widgets::shortcut_text(keys));
+                        ui.monospace(keys.unwrap_or_default());
+                        ui.end_row();
+                    }
+                });
+            }
         });
+        if let Some(i) = goto {
+            self.go(DatasetMovement::Goto(i), DatasetMovement::Goto(i), ctx);
+        }
+        if advance {
+            self.advance_anyway(ctx);
+        }
         egui::CentralPanel::default()
             .frame(egui::Frame::none().fill(Color32::BLACK))
             .show(ctx, |ui| {
@@ -262,6 +415,11 @@ impl eframe::App for Relabeling {
                 // // Handle clicks for bbs
                 // self.handle_img_response(img_response, ui);
 
+                // Typing in the search box should not trigger shortcuts
+                if ctx.wants_keyboard_input() {
+                    return;
+                }
+
                 // Handle prev next picture keyboard
                 self.handle_left_right(ctx);
 
@@ -271,6 +429,9 @@ impl eframe::App for Relabeling {
                 // Handle labels clearing
                 self.handle_clear(ctx);
 
+                // Handle skipping boxes and advancing with boxes missing
+                self.handle_skip(ctx);
+
                 // Handle filter mode
                 // let filter_pressed = ctx.input().key_pressed(egui::Key::F);
                 // if filter_pressed {
@@ -278,9 +439,20 @@ impl eframe::App for Relabeling {
                 // }
 
                 // Handle repeat button
-                if ctx.input().key_pressed(egui::Key::R) {
+                if self.bindings.pressed(ctx, Action::Repeat) {
                     self.repeat_bbs().unwrap();
                 }
+
+                // Handle cheat sheet
+                if keymap::cheat_sheet_pressed(ctx) {
+                    self.show_cheat_sheet = !self.show_cheat_sheet;
+                }
             });
+        widgets::cheat_sheet(
+            ctx,
+            &mut self.show_cheat_sheet,
+            &self.bindings,
+            &self.shortcuts,
+        );
     }
 }
@@ -0,0 +1,348 @@
+use anyhow::{anyhow, bail, Context, Result};
+use eframe::egui::Vec2;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::dataset::{BoundingBox, Dataset, Label, YoloBB};
+use crate::diff::class_name;
+
+/// A class given by its name in the label set or by its raw id.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ClassRef {
+    Id(usize),
+    Name(String),
+}
+
+impl ClassRef {
+    fn resolve<L: Label>(&self) -> Result<usize> {
+        match self {
+            ClassRef::Id(id) => Ok(*id),
+            ClassRef::Name(name) => L::from_name(name)
+                .map(|class| class.to_usize())
+                .ok_or_else(|| anyhow!("unknown class {name:?}")),
+        }
+    }
+}
+
+/// One rule of the rules file. A box is rewritten by the first rule matching its class and size.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    from: Vec<ClassRef>,
+    to: Option<ClassRef>,
+    #[serde(default)]
+    drop: bool,
+    // box size relative to the image size
+    min_width: Option<f32>,
+    max_width: Option<f32>,
+    min_height: Option<f32>,
+    max_height: Option<f32>,
+    min_area: Option<f32>,
+    max_area: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rule: Vec<RuleFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Relabel(usize),
+    Drop,
+}
+
+#[derive(Debug)]
+struct Rule {
+    from: Vec<usize>,
+    outcome: Outcome,
+    width: (f32, f32),
+    height: (f32, f32),
+    area: (f32, f32),
+    description: String,
+}
+
+impl Rule {
+    fn new<L: Label>(rule: RuleFile) -> Result<Self> {
+        let from = rule
+            .from
+            .iter()
+            .map(ClassRef::resolve::<L>)
+            .collect::<Result<Vec<_>>>()?;
+        if from.is_empty() {
+            bail!("rule without classes in from");
+        }
+        let outcome = match (&rule.to, rule.drop) {
+            (Some(to), false) => Outcome::Relabel(to.resolve::<L>()?),
+            (None, true) => Outcome::Drop,
+            _ => bail!("a rule needs exactly one of to or drop = true"),
+        };
+        let range = |min: Option<f32>, max: Option<f32>| {
+            (
+                min.unwrap_or(f32::NEG_INFINITY),
+                max.unwrap_or(f32::INFINITY),
+            )
+        };
+        let names: Vec<_> = from.iter().map(|id| class_name::<L>(*id)).collect();
+        let mut description = match outcome {
+            Outcome::Relabel(to) => format!("{} -> {}", names.join(", "), class_name::<L>(to)),
+            Outcome::Drop => format!("drop {}", names.join(", ")),
+        };
+        for (what, min, max) in [
+            ("width", rule.min_width, rule.max_width),
+            ("height", rule.min_height, rule.max_height),
+            ("area", rule.min_area, rule.max_area),
+        ] {
+            if let Some(min) = min {
+                description += &format!(" if {what} >= {min}");
+            }
+            if let Some(max) = max {
+                description += &format!(" if {what} <= {max}");
+            }
+        }
+        // boxes may come from stray ids, but must end up in a class of the label set
+        if let Outcome::Relabel(to) = outcome {
+            if to >= L::all().len() {
+                bail!("{description}: {to} is not a class id of the label set");
+            }
+        }
+        Ok(Rule {
+            from,
+            outcome,
+            width: range(rule.min_width, rule.max_width),
+            height: range(rule.min_height, rule.max_height),
+            area: range(rule.min_area, rule.max_area),
+            description,
+        })
+    }
+
+    fn matches<L: Label>(&self, bb: &YoloBB<L>) -> bool {
+        let in_range = |(min, max): (f32, f32), value: f32| min <= value && value <= max;
+        // the rect relative to the image size
+        let rect = bb.rect(Vec2::splat(1.0));
+        self.from.contains(&bb.class_id())
+            && in_range(self.width, rect.width())
+            && in_range(self.height, rect.height())
+            && in_range(self.area, rect.area())
+    }
+}
+
+fn load_rules<L: Label>(path: &Path) -> Result<Vec<Rule>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("could not read rules {path:?}"))?;
+    let file: RulesFile =
+        toml::from_str(&content).with_context(|| format!("invalid rules {path:?}"))?;
+    file.rule
+        .into_iter()
+        .enumerate()
+        .map(|(i, rule)| Rule::new::<L>(rule).with_context(|| format!("in rule {}", i + 1)))
+        .collect()
+}
+
+#[derive(Serialize)]
+struct Change {
+    file: String,
+    line: usize,
+    rule: usize,
+    before: String,
+    after: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RuleCount {
+    rule: String,
+    boxes: usize,
+}
+
+#[derive(Serialize)]
+struct Audit {
+    rules: String,
+    dry_run: bool,
+    files_changed: usize,
+    boxes_changed: usize,
+    per_rule: Vec<RuleCount>,
+    changes: Vec<Change>,
+}
+
+/// Rewrites the class ids of all labels in the dataset with the rules from `rules_path`.
+///
+/// Prints a diff of every changed label file and writes an audit of all changes to `report`.
+/// With `dry_run` no label file is touched.
+pub fn remap<L: Label>(
+    dataset: &Dataset<L>,
+    rules_path: &Path,
+    dry_run: bool,
+    report: &Path,
+) -> Result<()> {
+    let rules = load_rules::<L>(rules_path)?;
+    let mut counts = vec![0; rules.len()];
+    let mut changes = vec![];
+    let mut files_changed = 0;
+
+    for i in 0..dataset.len() {
+        let label = dataset.label(i)?;
+        let file = dataset.label_path(i).display().to_string();
+        let mut new_label = vec![];
+        let mut diff = vec![];
+        for (line, bb) in label.into_iter().enumerate() {
+            let Some(r) = rules.iter().position(|rule| rule.matches(&bb)) else {
+                new_label.push(bb);
+                continue;
+            };
+            counts[r] += 1;
+            let after = match rules[r].outcome {
+                Outcome::Relabel(to) => {
+                    let mut new_bb = bb;
+                    new_bb.set_class_id(to);
+                    new_label.push(new_bb);
+                    Some(new_bb.as_string())
+                }
+                Outcome::Drop => None,
+            };
+            if after.as_deref() == Some(&bb.as_string()) {
+                continue;
+            }
+            diff.push(format!("- {}", bb.as_string()));
+            diff.extend(after.iter().map(|after| format!("+ {after}")));
+            changes.push(Change {
+                file: file.clone(),
+                line: line + 1,
+                rule: r + 1,
+                before: bb.as_string(),
+                after,
+            });
+        }
+        if diff.is_empty() {
+            continue;
+        }
+        files_changed += 1;
+        println!("{file}");
+        for line in diff {
+            println!("  {line}");
+        }
+        if !dry_run {
+            dataset.save_label_at(i, new_label)?;
+        }
+    }
+
+    println!();
+    for (rule, count) in rules.iter().zip(&counts) {
+        println!("{count:>6} boxes: {}", rule.description);
+    }
+    let verb = if dry_run { "Would change" } else { "Changed" };
+    println!(
+        "{verb} {} boxes in {files_changed} of {} label files",
+        changes.len(),
+        dataset.len()
+    );
+
+    let audit = Audit {
+        rules: rules_path.display().to_string(),
+        dry_run,
+        files_changed,
+        boxes_changed: changes.len(),
+        per_rule: rules
+            .iter()
+            .zip(counts)
+            .map(|(rule, boxes)| RuleCount {
+                rule: rule.description.clone(),
+                boxes,
+            })
+            .collect(),
+        changes,
+    };
+    let file = std::fs::File::create(report)
+        .with_context(|| format!("could not create report {report:?}"))?;
+    serde_json::to_writer_pretty(file, &audit)?;
+    println!("Wrote audit report to {report:?}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Card;
+
+    fn rules(toml: &str) -> Result<Vec<Rule>> {
+        let file: RulesFile = toml::from_str(toml)?;
+        file.rule.into_iter().map(Rule::new::<Card>).collect()
+    }
+
+    fn bb(line: &str) -> YoloBB<Card> {
+        line.parse().unwrap()
+    }
+
+    /// Index of the first rule matching the box, as `remap` applies them.
+    fn first_match(rules: &[Rule], line: &str) -> Option<usize> {
+        rules.iter().position(|rule| rule.matches(&bb(line)))
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = rules(
+            r#"
+            [[rule]]
+            from = ["K", "Q"]
+            drop = true
+            max_area = 0.01
+            [[rule]]
+            from = ["K"]
+            to = "A"
+            [[rule]]
+            from = ["K", "J"]
+            to = "10"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rules[0].outcome, Outcome::Drop);
+        assert_eq!(rules[1].outcome, Outcome::Relabel(0));
+        // small kings are dropped, the others relabeled by the earlier of the two rules
+        assert_eq!(first_match(&rules, "1 0.5 0.5 0.05 0.05"), Some(0));
+        assert_eq!(first_match(&rules, "1 0.5 0.5 0.2 0.2"), Some(1));
+        assert_eq!(first_match(&rules, "3 0.5 0.5 0.2 0.2"), Some(2));
+        assert_eq!(first_match(&rules, "2 0.5 0.5 0.2 0.2"), None);
+        assert_eq!(first_match(&rules, "0 0.5 0.5 0.05 0.05"), None);
+    }
+
+    #[test]
+    fn size_conditions_are_inclusive_and_relative() {
+        let rules = rules(
+            r#"
+            [[rule]]
+            from = [1]
+            to = 0
+            min_width = 0.25
+            max_height = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(first_match(&rules, "1 0.5 0.5 0.25 0.5"), Some(0));
+        assert_eq!(first_match(&rules, "1 0.5 0.5 0.2 0.5"), None);
+        assert_eq!(first_match(&rules, "1 0.5 0.5 0.25 0.6"), None);
+        assert_eq!(
+            rules[0].description,
+            "K -> A if width >= 0.25 if height <= 0.5"
+        );
+    }
+
+    #[test]
+    fn stray_ids_can_be_remapped_into_the_label_set() {
+        let rules = rules("[[rule]]\nfrom = [40]\nto = \"A\"\n").unwrap();
+        assert_eq!(first_match(&rules, "40 0.5 0.5 0.2 0.2"), Some(0));
+        assert_eq!(rules[0].description, "class 40 -> A");
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let error = |toml: &str| rules(toml).unwrap_err().to_string();
+        assert!(error("[[rule]]\nfrom = [\"A\"]\nto = 13\n").contains("13 is not a class id"));
+        assert!(error("[[rule]]\nfrom = [\"A\"]\nto = \"Z\"\n").contains("unknown class"));
+        assert!(error("[[rule]]\nfrom = []\ndrop = true\n").contains("without classes"));
+        assert!(error("[[rule]]\nfrom = [\"A\"]\n").contains("exactly one of"));
+        assert!(
+            error("[[rule]]\nfrom = [\"A\"]\nto = \"K\"\ndrop = true\n").contains("exactly one of")
+        );
+    }
+}
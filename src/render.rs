@@ -0,0 +1,261 @@
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use eframe::egui::{Color32, FontDefinitions, Rect, Vec2};
+use image::imageops::FilterType;
+use image::{Rgb, RgbImage};
+use std::path::Path;
+
+use crate::dataset::{BoundingBox, Dataset, Label};
+
+/// Background of the cells of a contact sheet.
+const SHEET_BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Jpg,
+    Png,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Jpg => "jpg",
+            Format::Png => "png",
+        }
+    }
+}
+
+/// Parses the `COLUMNSxROWS` size of a contact sheet.
+pub fn parse_grid(grid: &str) -> std::result::Result<(u32, u32), String> {
+    let parse = |n: &str| n.trim().parse::<u32>().ok().filter(|n| *n > 0);
+    match grid.split_once('x') {
+        Some((columns, rows)) => match (parse(columns), parse(rows)) {
+            (Some(columns), Some(rows)) => Ok((columns, rows)),
+            _ => Err(format!("invalid grid {grid:?}, expected e.g. 4x3")),
+        },
+        None => Err(format!("invalid grid {grid:?}, expected e.g. 4x3")),
+    }
+}
+
+fn rgb(color: Color32) -> Rgb<u8> {
+    Rgb([color.r(), color.g(), color.b()])
+}
+
+/// Draws boxes and text onto images, without a window.
+pub struct Painter {
+    font: FontArc,
+}
+
+impl Painter {
+    /// Uses the monospace font of egui, the one the app tags the boxes with.
+    pub fn new() -> Result<Self> {
+        let mut fonts = FontDefinitions::default();
+        let font = fonts
+            .font_data
+            .remove("Hack")
+            .ok_or_else(|| anyhow!("the monospace font of egui is missing"))?;
+        let font = FontArc::try_from_vec(font.font.into_owned())?;
+        Ok(Painter { font })
+    }
+
+    fn fill(&self, image: &mut RgbImage, rect: Rect, color: Rgb<u8>) {
+        let bounds = rect.intersect(Rect::from_min_size(
+            Default::default(),
+            Vec2::new(image.width() as f32, image.height() as f32),
+        ));
+        if bounds.width() <= 0.0 || bounds.height() <= 0.0 {
+            return;
+        }
+        for y in bounds.top().round() as u32..bounds.bottom().round() as u32 {
+            for x in bounds.left().round() as u32..bounds.right().round() as u32 {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    fn stroke(&self, image: &mut RgbImage, rect: Rect, width: f32, color: Rgb<u8>) {
+        let inner = rect.shrink(width);
+        let (left, right, top, bottom) = (rect.left(), rect.right(), rect.top(), rect.bottom());
+        self.fill(
+            image,
+            Rect::from_x_y_ranges(left..=right, top..=inner.top()),
+            color,
+        );
+        self.fill(
+            image,
+            Rect::from_x_y_ranges(left..=right, inner.bottom()..=bottom),
+            color,
+        );
+        self.fill(
+            image,
+            Rect::from_x_y_ranges(left..=inner.left(), top..=bottom),
+            color,
+        );
+        self.fill(
+            image,
+            Rect::from_x_y_ranges(inner.right()..=right, top..=bottom),
+            color,
+        );
+    }
+
+    /// Draws `text` on a filled tag whose bottom left corner is at `pos`, like the app does.
+    fn tag(&self, image: &mut RgbImage, pos: Vec2, text: &str, size: f32, background: Rgb<u8>) {
+        let font = self.font.as_scaled(PxScale::from(size));
+        let padding = size * 0.15;
+        let width: f32 = text.chars().map(|c| font.h_advance(font.glyph_id(c))).sum();
+        let tag = Rect::from_min_max(
+            [pos.x, pos.y - font.height() - padding].into(),
+            [pos.x + width + 2.0 * padding, pos.y].into(),
+        );
+        self.fill(image, tag, background);
+
+        let mut x = pos.x + padding;
+        let baseline = pos.y + font.descent() - padding / 2.0;
+        for c in text.chars() {
+            let mut glyph = font.scaled_glyph(c);
+            glyph.position = point(x, baseline);
+            x += font.h_advance(glyph.id);
+            let Some(outline) = self.font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                let (px, py) = (
+                    bounds.min.x as i64 + gx as i64,
+                    bounds.min.y as i64 + gy as i64,
+                );
+                if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+                    return;
+                }
+                // black text
+                let pixel = image.get_pixel_mut(px as u32, py as u32);
+                for channel in pixel.0.iter_mut() {
+                    *channel = (*channel as f32 * (1.0 - coverage)) as u8;
+                }
+            });
+        }
+    }
+
+    /// The image with its boxes and class name tags.
+    pub fn annotate<L: Label>(&self, dataset: &Dataset<L>, i: usize) -> Result<RgbImage> {
+        let path = dataset.image_path(i);
+        let mut image = image::open(path)
+            .with_context(|| format!("could not open {path:?}"))?
+            .to_rgb8();
+        let size = Vec2::new(image.width() as f32, image.height() as f32);
+        // readable on thumbnails too
+        let longer = size.x.max(size.y);
+        let (line, text) = ((longer / 640.0).max(2.0), (longer / 40.0).max(16.0));
+        // boxes of unknown classes have no color or name
+        let classes = L::all().len();
+        for bb in dataset
+            .label(i)?
+            .iter()
+            .filter(|bb| bb.class_id() < classes)
+        {
+            let rect = bb.rect(size);
+            let color = rgb(bb.class().color());
+            self.stroke(&mut image, rect, line, color);
+            let pos = rect.left_bottom().to_vec2();
+            self.tag(&mut image, pos, &bb.class().to_name(), text, color);
+        }
+        Ok(image)
+    }
+}
+
+/// Fits the image into a `cell` pixels square, centered on the sheet background.
+pub fn fit(image: &RgbImage, cell: u32) -> RgbImage {
+    let scale = cell as f32 / image.width().max(image.height()) as f32;
+    let (width, height) = (
+        ((image.width() as f32 * scale) as u32).max(1),
+        ((image.height() as f32 * scale) as u32).max(1),
+    );
+    let resized = image::imageops::resize(image, width, height, FilterType::Triangle);
+    let mut square = RgbImage::from_pixel(cell, cell, SHEET_BACKGROUND);
+    let (x, y) = ((cell - width) / 2, (cell - height) / 2);
+    image::imageops::replace(&mut square, &resized, x as i64, y as i64);
+    square
+}
+
+/// Writes copies of the labeled images with their boxes drawn onto them to `out`, or with
+/// `sheet` contact sheets of that many columns and rows of images, `cell` pixels each.
+///
+/// With `class_names` only the images with a box of one of these classes are rendered.
+pub fn render<L: Label>(
+    dataset: &Dataset<L>,
+    out: &Path,
+    format: Format,
+    class_names: &[String],
+    sheet: Option<(u32, u32)>,
+    cell: u32,
+) -> Result<()> {
+    // compared by id, as boxes of unknown classes have no class
+    let classes = class_names
+        .iter()
+        .map(|name| {
+            L::from_name(name)
+                .map(L::to_usize)
+                .ok_or_else(|| anyhow!("unknown class {name:?}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut images = vec![];
+    for i in (0..dataset.len()).filter(|i| dataset.has_label(*i)) {
+        let label = dataset.label(i)?;
+        if classes.is_empty() || label.iter().any(|bb| classes.contains(&bb.class_id())) {
+            images.push(i);
+        }
+    }
+    if images.is_empty() {
+        bail!("no labeled images to render");
+    }
+    std::fs::create_dir_all(out)?;
+    let painter = Painter::new()?;
+    let extension = format.extension();
+
+    let Some((columns, rows)) = sheet else {
+        for i in &images {
+            let stem = dataset
+                .image_path(*i)
+                .file_stem()
+                .unwrap()
+                .to_string_lossy();
+            let path = out.join(format!("{stem}.{extension}"));
+            painter
+                .annotate(dataset, *i)?
+                .save(&path)
+                .with_context(|| format!("could not write {path:?}"))?;
+        }
+        println!("Rendered {} images to {out:?}", images.len());
+        return Ok(());
+    };
+
+    let per_sheet = (columns * rows) as usize;
+    let sheets = images.chunks(per_sheet).collect::<Vec<_>>();
+    let caption = (cell as f32 / 16.0).max(10.0);
+    for (s, chunk) in sheets.iter().enumerate() {
+        let mut sheet = RgbImage::from_pixel(columns * cell, rows * cell, SHEET_BACKGROUND);
+        for (c, i) in chunk.iter().enumerate() {
+            let (x, y) = ((c as u32 % columns) * cell, (c as u32 / columns) * cell);
+            let thumbnail = fit(&painter.annotate(dataset, *i)?, cell);
+            image::imageops::replace(&mut sheet, &thumbnail, x as i64, y as i64);
+            let name = dataset
+                .image_path(*i)
+                .file_name()
+                .unwrap()
+                .to_string_lossy();
+            let pos = Vec2::new(x as f32, (y + cell) as f32);
+            painter.tag(&mut sheet, pos, &name, caption, Rgb([220, 220, 220]));
+        }
+        let path = out.join(format!("sheet_{:03}.{extension}", s + 1));
+        sheet
+            .save(&path)
+            .with_context(|| format!("could not write {path:?}"))?;
+    }
+    println!(
+        "Rendered {} images on {} contact sheets to {out:?}",
+        images.len(),
+        sheets.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use eframe::egui::{Color32, Rect, Vec2};
+use image::{DynamicImage, ImageOutputFormat};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::dataset::{BoundingBox, Dataset, Label, YoloBB};
+use crate::matching::iou;
+use crate::render::{self, Painter};
+
+/// Boxes of the same class overlapping more than this are reported as duplicates.
+const DUPLICATE_IOU: f32 = 0.9;
+/// Boxes with a side shorter than this many pixels are reported as tiny.
+const MIN_SIDE: f32 = 2.0;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; }
+td, th { padding: 0.3em 0.8em; border-bottom: 1px solid #ddd; text-align: right; }
+td:first-child, th:first-child { text-align: left; }
+.swatch { display: inline-block; width: 1em; height: 1em; vertical-align: middle; }
+.gallery { display: flex; flex-wrap: wrap; gap: 0.5em; }
+figure { margin: 0; }
+figcaption { font-size: 0.8em; }
+";
+
+#[derive(Default)]
+struct ClassStats {
+    boxes: usize,
+    /// Images with a box of the class
+    images: Vec<usize>,
+    /// Sum of the box areas relative to their image
+    area: f32,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn hex(color: Color32) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r(), color.g(), color.b())
+}
+
+/// Problems of the boxes of a label that are worth a look.
+fn check_label<L: Label>(label: &[YoloBB<L>], size: Vec2) -> Vec<String> {
+    let mut warnings = vec![];
+    let image = Rect::from_min_size(Default::default(), size).expand(0.5);
+    for (b, bb) in label.iter().enumerate() {
+        if bb.class_id() >= L::all().len() {
+            warnings.push(format!(
+                "box {b} has the unknown class id {}",
+                bb.class_id()
+            ));
+            continue;
+        }
+        let name = bb.class().to_name();
+        let rect = bb.rect(size);
+        if !image.contains_rect(rect) {
+            warnings.push(format!("box {b} ({name}) is not inside the image"));
+        }
+        if rect.width() < MIN_SIDE || rect.height() < MIN_SIDE {
+            warnings.push(format!(
+                "box {b} ({name}) is smaller than {MIN_SIDE} pixels"
+            ));
+        }
+        for (a, other) in label[..b].iter().enumerate() {
+            if other.class_id() == bb.class_id() && iou(other.rect(size), rect) > DUPLICATE_IOU {
+                warnings.push(format!("boxes {a} and {b} ({name}) are duplicates"));
+            }
+        }
+    }
+    warnings
+}
+
+/// Writes a self-contained HTML page with statistics of the dataset, a gallery of up to
+/// `per_class` images for each class and the warnings of checking the labels.
+pub fn report<L: Label>(
+    dataset: &Dataset<L>,
+    out: &Path,
+    per_class: usize,
+    thumbnail: u32,
+) -> Result<()> {
+    let mut stats: Vec<ClassStats> = L::all().iter().map(|_| ClassStats::default()).collect();
+    let mut warnings: Vec<(String, String)> = vec![];
+    let (mut labeled, mut unlabeled, mut boxes) = (0, 0, 0);
+    for i in 0..dataset.len() {
+        let name = dataset.image_path(i).file_name().unwrap().to_string_lossy();
+        for flag in dataset.state(i).flags {
+            warnings.push((name.to_string(), flag.description().to_string()));
+        }
+        if !dataset.has_label(i) {
+            unlabeled += 1;
+            continue;
+        }
+        let label = match dataset.label(i) {
+            Ok(label) => label,
+            Err(e) => {
+                warnings.push((name.to_string(), format!("unreadable label: {e}")));
+                continue;
+            }
+        };
+        labeled += 1;
+        boxes += label.len();
+        let size = dataset.image_size(i)?;
+        for warning in check_label(&label, size) {
+            warnings.push((name.to_string(), warning));
+        }
+        let known = stats.len();
+        for bb in label.iter().filter(|bb| bb.class_id() < known) {
+            let class = &mut stats[bb.class_id()];
+            class.boxes += 1;
+            class.area += bb.rect(size).area() / size.x / size.y;
+            if class.images.last() != Some(&i) {
+                class.images.push(i);
+            }
+        }
+    }
+
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+    )?;
+    writeln!(
+        html,
+        "<title>Labeling report</title>\n<style>{STYLE}</style>"
+    )?;
+    writeln!(html, "</head>\n<body>\n<h1>Labeling report</h1>")?;
+
+    writeln!(html, "<h2>Statistics</h2>\n<table>")?;
+    let per_image = if labeled > 0 {
+        boxes as f32 / labeled as f32
+    } else {
+        0.0
+    };
+    for (name, value) in [
+        ("Images", dataset.len().to_string()),
+        ("Labeled images", labeled.to_string()),
+        ("Unlabeled images", unlabeled.to_string()),
+        ("Boxes", boxes.to_string()),
+        ("Boxes per labeled image", format!("{per_image:.2}")),
+    ] {
+        writeln!(html, "<tr><td>{name}</td><td>{value}</td></tr>")?;
+    }
+    writeln!(html, "</table>")?;
+
+    writeln!(html, "<h2>Classes</h2>\n<table>")?;
+    writeln!(
+        html,
+        "<tr><th>Class</th><th>Boxes</th><th>Images</th><th>Mean box area</th></tr>"
+    )?;
+    for (class, stats) in L::all().into_iter().zip(&stats) {
+        let area = if stats.boxes > 0 {
+            format!("{:.2}%", 100.0 * stats.area / stats.boxes as f32)
+        } else {
+            "-".into()
+        };
+        writeln!(
+            html,
+            "<tr><td><span class=\"swatch\" style=\"background: {}\"></span> \
+             <a href=\"#class-{}\">{}</a></td><td>{}</td><td>{}</td><td>{area}</td></tr>",
+            hex(class.color()),
+            class.to_usize(),
+            escape(&class.to_name()),
+            stats.boxes,
+            stats.images.len(),
+        )?;
+    }
+    writeln!(html, "</table>")?;
+
+    writeln!(html, "<h2>Warnings ({})</h2>", warnings.len())?;
+    if warnings.is_empty() {
+        writeln!(html, "<p>No warnings.</p>")?;
+    } else {
+        writeln!(html, "<ul>")?;
+        for (name, warning) in &warnings {
+            writeln!(
+                html,
+                "<li><b>{}</b>: {}</li>",
+                escape(name),
+                escape(warning)
+            )?;
+        }
+        writeln!(html, "</ul>")?;
+    }
+
+    writeln!(html, "<h2>Galleries</h2>")?;
+    let painter = Painter::new()?;
+    let mut thumbnails: HashMap<usize, String> = HashMap::new();
+    for (class, stats) in L::all().into_iter().zip(&stats) {
+        if stats.images.is_empty() {
+            continue;
+        }
+        let shown = stats.images.len().min(per_class);
+        writeln!(
+            html,
+            "<h3 id=\"class-{}\">{} ({shown} of {} images)</h3>\n<div class=\"gallery\">",
+            class.to_usize(),
+            escape(&class.to_name()),
+            stats.images.len(),
+        )?;
+        for i in &stats.images[..shown] {
+            if let Entry::Vacant(entry) = thumbnails.entry(*i) {
+                let image = render::fit(&painter.annotate(dataset, *i)?, thumbnail);
+                let mut jpeg = Cursor::new(vec![]);
+                DynamicImage::ImageRgb8(image)
+                    .write_to(&mut jpeg, ImageOutputFormat::Jpeg(80))
+                    .with_context(|| format!("could not encode a thumbnail of image {i}"))?;
+                let encoded = base64::engine::general_purpose::STANDARD.encode(jpeg.get_ref());
+                entry.insert(encoded);
+            }
+            let name = dataset
+                .image_path(*i)
+                .file_name()
+                .unwrap()
+                .to_string_lossy();
+            writeln!(
+                html,
+                "<figure><img src=\"data:image/jpeg;base64,{}\" width=\"{thumbnail}\" \
+                 height=\"{thumbnail}\"><figcaption>{}</figcaption></figure>",
+                thumbnails[i],
+                escape(&name),
+            )?;
+        }
+        writeln!(html, "</div>")?;
+    }
+    writeln!(html, "</body>\n</html>")?;
+
+    std::fs::write(out, html).with_context(|| format!("could not write {out:?}"))?;
+    println!(
+        "Wrote the report of {labeled} labeled images with {} warnings to {out:?}",
+        warnings.len()
+    );
+    Ok(())
+}
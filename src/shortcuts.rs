@@ -0,0 +1,91 @@
+use eframe::egui;
+use egui::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::dataset::Label;
+
+/// Seconds to wait for the next key of a sequence before giving up on it.
+pub const SEQUENCE_TIMEOUT: f64 = 1.0;
+
+/// Buffers key presses across frames so that multi-key shortcuts like `H` then `1` can be typed
+/// one key after another.
+pub struct KeySequence {
+    keys: Vec<Key>,
+    last_press: f64,
+    timeout: f64,
+}
+
+impl KeySequence {
+    pub fn new(timeout: f64) -> Self {
+        KeySequence {
+            keys: vec![],
+            last_press: 0.0,
+            timeout,
+        }
+    }
+
+    /// Keys typed so far of a sequence that is not complete yet.
+    pub fn pending(&self) -> &[Key] {
+        &self.keys
+    }
+
+    /// Feeds the keys pressed this frame and returns the classes whose sequence got completed.
+    ///
+    /// A sequence completes as soon as no longer shortcut starts with it. If it is also the prefix
+    /// of a longer shortcut, it completes after the timeout.
+    pub fn update<L: Label>(&mut self, ctx: &Context, shortcuts: &HashMap<Vec<Key>, L>) -> Vec<L> {
+        let now = ctx.input().time;
+        let mut completed = vec![];
+        if !self.keys.is_empty() && now - self.last_press > self.timeout {
+            completed.extend(shortcuts.get(&self.keys));
+            self.keys.clear();
+        }
+
+        let pressed: Vec<Key> = ctx
+            .input()
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Key {
+                    key,
+                    pressed: true,
+                    modifiers,
+                } if modifiers.is_none() => Some(*key),
+                _ => None,
+            })
+            .collect();
+        for key in pressed {
+            if !shortcuts.keys().any(|keys| keys.contains(&key)) {
+                self.keys.clear();
+                continue;
+            }
+            self.last_press = now;
+            self.keys.push(key);
+            if !is_prefix(&self.keys, shortcuts) {
+                // start over with the new key as the beginning of a sequence
+                self.keys = vec![key];
+            }
+            let extendable = shortcuts
+                .keys()
+                .any(|keys| keys.len() > self.keys.len() && keys.starts_with(&self.keys));
+            if let Some(class) = shortcuts.get(&self.keys) {
+                if !extendable {
+                    completed.push(*class);
+                    self.keys.clear();
+                }
+            } else if !extendable {
+                self.keys.clear();
+            }
+        }
+
+        if !self.keys.is_empty() {
+            ctx.request_repaint_after(Duration::from_secs_f64(self.timeout));
+        }
+        completed
+    }
+}
+
+fn is_prefix<L>(keys: &[Key], shortcuts: &HashMap<Vec<Key>, L>) -> bool {
+    shortcuts.keys().any(|shortcut| shortcut.starts_with(keys))
+}
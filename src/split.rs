@@ -0,0 +1,328 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::dataset::{Dataset, Label};
+use crate::datayaml::DataYaml;
+use crate::imagehash;
+
+const SPLITS: [&str; 3] = ["train", "val", "test"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SplitMode {
+    /// Symlink the images and labels into the split directories
+    Symlink,
+    /// Copy the images and labels into the split directories
+    Copy,
+    /// Only list the image paths of each split, the labels stay next to the images
+    List,
+}
+
+/// Images that go into the same split, with the number of them containing each class.
+struct Group {
+    images: Vec<usize>,
+    classes: BTreeMap<usize, usize>,
+}
+
+/// Groups the images whose hashes differ by at most `max_distance` bits from the first image of
+/// a group, the closest one if there are several.
+///
+/// Only the first image of a group is compared, so a video whose frames drift apart slowly is
+/// cut into several groups rather than chained into one, and an image is compared with one
+/// image per group instead of with every other image.
+fn near_duplicates(hashes: &[u64], max_distance: u32) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for (i, hash) in hashes.iter().enumerate() {
+        let closest = groups
+            .iter_mut()
+            .map(|group| (imagehash::distance(hashes[group[0]], *hash), group))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by_key(|(distance, _)| *distance);
+        match closest {
+            Some((_, group)) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+/// Assigns each group to a split so that the images of every class are spread by the ratios.
+///
+/// Groups with the rarest classes are placed first, each into the split that misses the most
+/// images of its classes. Groups without boxes fill up the splits that miss the most images.
+fn assign(groups: &[Group], ratios: &[f32], rng: &mut StdRng) -> Vec<usize> {
+    let mut totals: BTreeMap<usize, usize> = BTreeMap::new();
+    for group in groups {
+        for (class, count) in &group.classes {
+            *totals.entry(*class).or_default() += count;
+        }
+    }
+    let images: usize = groups.iter().map(|group| group.images.len()).sum();
+
+    let mut order: Vec<usize> = (0..groups.len()).collect();
+    order.shuffle(rng);
+    order.sort_by_key(|g| groups[*g].classes.keys().map(|c| totals[c]).min());
+    // groups without boxes last
+    let without_boxes = order.partition_point(|g| groups[*g].classes.is_empty());
+    order.rotate_left(without_boxes);
+
+    let mut assigned = vec![0; groups.len()];
+    let mut have_classes = vec![BTreeMap::<usize, usize>::new(); ratios.len()];
+    let mut have_images = vec![0; ratios.len()];
+    for g in order {
+        let group = &groups[g];
+        let class_need = |s: usize| -> f32 {
+            group
+                .classes
+                .keys()
+                .map(|c| {
+                    let have = have_classes[s].get(c).copied().unwrap_or(0);
+                    ratios[s] * totals[c] as f32 - have as f32
+                })
+                .sum()
+        };
+        let image_need = |s: usize| ratios[s] * images as f32 - have_images[s] as f32;
+        let split = (0..ratios.len())
+            .max_by(|a, b| {
+                class_need(*a)
+                    .total_cmp(&class_need(*b))
+                    .then(image_need(*a).total_cmp(&image_need(*b)))
+            })
+            .unwrap();
+        assigned[g] = split;
+        have_images[split] += group.images.len();
+        for (class, count) in &group.classes {
+            *have_classes[split].entry(*class).or_default() += count;
+        }
+    }
+    assigned
+}
+
+#[cfg(unix)]
+fn link(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(src, dst)
+}
+#[cfg(windows)]
+fn link(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(src, dst)
+}
+
+/// Splits the labeled images into the Ultralytics layout `images/<split>` and `labels/<split>`
+/// in `out`, next to a `data.yaml` describing it.
+///
+/// `ratios` are the parts of the train, val and optional test split. Splits are stratified by
+/// the classes in the images and near-duplicate images, by the distance of their image hashes,
+/// always end up in the same split.
+pub fn split<L: Label>(
+    dataset: &Dataset<L>,
+    out: &Path,
+    ratios: &[f32],
+    seed: u64,
+    mode: SplitMode,
+    max_distance: u32,
+) -> Result<()> {
+    if !(2..=SPLITS.len()).contains(&ratios.len()) {
+        bail!("expected 2 or 3 ratios for train, val and test, got {ratios:?}");
+    }
+    let sum: f32 = ratios.iter().sum();
+    if ratios.iter().any(|ratio| *ratio < 0.0) || sum <= 0.0 {
+        bail!("ratios must be positive, got {ratios:?}");
+    }
+    let ratios: Vec<f32> = ratios.iter().map(|ratio| ratio / sum).collect();
+    if out
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        bail!("{out:?} is not empty, remove it or choose another --out");
+    }
+
+    let labeled: Vec<usize> = (0..dataset.len())
+        .filter(|i| dataset.has_label(*i))
+        .collect();
+    if labeled.is_empty() {
+        bail!("no labeled images to split");
+    }
+    let hashes = labeled
+        .iter()
+        .map(|i| {
+            let path = dataset.image_path(*i);
+            imagehash::dhash(path).with_context(|| format!("could not hash {path:?}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let groups = near_duplicates(&hashes, max_distance)
+        .into_iter()
+        .map(|members| {
+            let images: Vec<usize> = members.into_iter().map(|m| labeled[m]).collect();
+            let mut classes = BTreeMap::new();
+            for i in &images {
+                let present: BTreeSet<usize> =
+                    dataset.label(*i)?.iter().map(|bb| bb.class_id()).collect();
+                for class in present {
+                    *classes.entry(class).or_default() += 1;
+                }
+            }
+            Ok(Group { images, classes })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let assigned = assign(&groups, &ratios, &mut StdRng::seed_from_u64(seed));
+
+    let mut splits = vec![vec![]; ratios.len()];
+    for (group, split) in groups.iter().zip(assigned) {
+        splits[split].extend(group.images.iter().copied());
+    }
+
+    if mode == SplitMode::List
+        && labeled.iter().any(|i| {
+            let dir = |path: &Path| path.parent().and_then(|dir| dir.canonicalize().ok());
+            dir(dataset.label_path(*i)) != dir(dataset.image_path(*i))
+        })
+    {
+        println!("The labels are not next to the images, the listed images will have no labels");
+    }
+    let out_abs = {
+        fs::create_dir_all(out)?;
+        out.canonicalize()?
+    };
+    let mut data_yaml = DataYaml::for_label_set::<L>();
+    data_yaml.path = Some(out_abs.clone());
+    for (s, images) in splits.iter_mut().enumerate() {
+        images.sort();
+        let name = SPLITS[s];
+        let entry = match mode {
+            SplitMode::List => {
+                let mut list = String::new();
+                for i in images.iter() {
+                    list += &format!("{}\n", dataset.image_path(*i).canonicalize()?.display());
+                }
+                fs::write(out.join(format!("{name}.txt")), list)?;
+                format!("{name}.txt")
+            }
+            SplitMode::Symlink | SplitMode::Copy => {
+                let (image_dir, label_dir) =
+                    (out.join("images").join(name), out.join("labels").join(name));
+                fs::create_dir_all(&image_dir)?;
+                fs::create_dir_all(&label_dir)?;
+                for i in images.iter() {
+                    let (image, label) = (dataset.image_path(*i), dataset.label_path(*i));
+                    let image_dst = image_dir.join(image.file_name().unwrap());
+                    let label_dst = label_dir.join(label.file_name().unwrap());
+                    if mode == SplitMode::Copy {
+                        fs::copy(image, &image_dst)?;
+                        fs::copy(label, &label_dst)?;
+                    } else {
+                        link(&image.canonicalize()?, &image_dst)?;
+                        link(&label.canonicalize()?, &label_dst)?;
+                    }
+                }
+                format!("images/{name}")
+            }
+        };
+        match s {
+            0 => data_yaml.train = Some(entry.into()),
+            1 => data_yaml.val = Some(entry.into()),
+            _ => data_yaml.test = Some(entry.into()),
+        }
+        println!("{name}: {} images", images.len());
+    }
+    data_yaml.save(&out.join("data.yaml"))?;
+
+    let skipped = dataset.len() - labeled.len();
+    if skipped > 0 {
+        println!("Skipped {skipped} images without labels");
+    }
+    let duplicates = labeled.len() - groups.len();
+    if duplicates > 0 {
+        println!("Kept {duplicates} near-duplicate images in the split of their look-alikes");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn near_duplicates_do_not_chain() {
+        // every hash is one bit away from the previous one
+        let hashes = [0b0000, 0b0001, 0b0011, 0b0111, 0b1111, 0xff00];
+        assert_eq!(
+            near_duplicates(&hashes, 1),
+            [vec![0, 1], vec![2, 3], vec![4], vec![5]]
+        );
+        assert_eq!(
+            near_duplicates(&hashes, 2),
+            [vec![0, 1, 2], vec![3, 4], vec![5]]
+        );
+        assert_eq!(near_duplicates(&hashes, 0).len(), hashes.len());
+    }
+
+    #[test]
+    fn near_duplicates_join_the_closest_group() {
+        let hashes = [0b0000, 0b1110, 0b1000];
+        assert_eq!(near_duplicates(&hashes, 2), [vec![0, 2], vec![1]]);
+        let hashes = [0b0000, 0b1110, 0b1100];
+        assert_eq!(near_duplicates(&hashes, 2), [vec![0], vec![1, 2]]);
+    }
+
+    fn group(images: &[usize], classes: &[usize]) -> Group {
+        Group {
+            images: images.to_vec(),
+            classes: classes.iter().map(|class| (*class, images.len())).collect(),
+        }
+    }
+
+    /// Images per split, and per split and class.
+    fn counts(
+        groups: &[Group],
+        assigned: &[usize],
+        splits: usize,
+    ) -> (Vec<usize>, Vec<Vec<usize>>) {
+        let mut images = vec![0; splits];
+        let mut classes = vec![vec![0; 2]; splits];
+        for (group, split) in groups.iter().zip(assigned) {
+            images[*split] += group.images.len();
+            for (class, count) in &group.classes {
+                classes[*split][*class] += count;
+            }
+        }
+        (images, classes)
+    }
+
+    #[test]
+    fn every_class_is_spread_by_the_ratios() {
+        let groups = [
+            group(&[0], &[0]),
+            group(&[1], &[0]),
+            group(&[2, 3], &[0]),
+            group(&[4], &[0, 1]),
+            group(&[5], &[1]),
+            group(&[6], &[]),
+            group(&[7], &[]),
+            group(&[8], &[0]),
+        ];
+        for seed in 0..10 {
+            let assigned = assign(&groups, &[0.5, 0.5], &mut StdRng::seed_from_u64(seed));
+            let (images, classes) = counts(&groups, &assigned, 2);
+            assert_eq!(images.iter().min(), Some(&4), "seed {seed}");
+            assert_eq!(classes[0][1], 1, "seed {seed}");
+            assert_eq!(classes[1][1], 1, "seed {seed}");
+            assert!(classes[0][0].abs_diff(classes[1][0]) <= 2, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn images_without_boxes_fill_up_the_splits() {
+        let mut groups: Vec<Group> = (0..8).map(|i| group(&[i], &[0])).collect();
+        groups.extend((8..10).map(|i| group(&[i], &[])));
+        let assigned = assign(&groups, &[0.8, 0.1, 0.1], &mut StdRng::seed_from_u64(42));
+        let (images, classes) = counts(&groups, &assigned, 3);
+        assert_eq!(images, [8, 1, 1]);
+        assert_eq!(classes[0][0] + classes[1][0] + classes[2][0], 8);
+        assert!(classes[0][0] >= 6);
+    }
+}
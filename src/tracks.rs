@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use eframe::egui::color::Hsva;
+use eframe::egui::Color32;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+
+use crate::dataset::{BoundingBox, Dataset, Label, YoloLabel};
+use crate::matching::Matcher;
+
+/// Distinct color of a track, neighbouring ids get far apart hues.
+pub fn track_color(track: u32) -> Color32 {
+    let hue = (track as f32 * 0.618_034).fract();
+    Hsva::new(hue, 0.85, 0.95, 1.0).into()
+}
+
+/// Gives the boxes of `current` the track ids of the matching boxes of the same class in
+/// `previous`. Boxes without a match keep their id, or get a new one if they have none.
+pub fn propagate_ids<L: Label>(
+    previous: &YoloLabel<L>,
+    current: &mut YoloLabel<L>,
+    matcher: &Matcher,
+    next_track: &mut u32,
+) {
+    let matches: Vec<_> = matcher
+        .match_boxes(previous, current)
+        .into_iter()
+        .filter(|m| {
+            previous[m.a].track().is_some() && previous[m.a].class() == current[m.b].class()
+        })
+        .collect();
+    for m in &matches {
+        current[m.b].set_track(previous[m.a].track());
+    }
+    let propagated: HashSet<_> = matches.iter().map(|m| m.b).collect();
+    let taken: HashSet<_> = matches
+        .iter()
+        .filter_map(|m| previous[m.a].track())
+        .collect();
+    for (i, bb) in current.iter_mut().enumerate() {
+        let duplicate = bb.track().is_some_and(|track| taken.contains(&track));
+        if !propagated.contains(&i) && (bb.track().is_none() || duplicate) {
+            bb.set_track(Some(*next_track));
+            *next_track += 1;
+        }
+    }
+}
+
+/// Writes the boxes with a track id in the MOTChallenge ground truth format, one line per box:
+/// `frame, id, left, top, width, height, conf, class, visibility` with pixel coordinates,
+/// frames counted from 1 and the track and class ids plus 1 as MOT ids start at 1.
+pub fn export_mot<L: Label>(dataset: &Dataset<L>, out: &Path) -> Result<()> {
+    let mut file =
+        std::fs::File::create(out).with_context(|| format!("could not create {out:?}"))?;
+    let (mut boxes, mut untracked) = (0, 0);
+    for i in 0..dataset.len() {
+        let label = dataset.label(i)?;
+        if label.is_empty() {
+            continue;
+        }
+        let size = dataset.image_size(i)?;
+        for bb in label {
+            let Some(track) = bb.track() else {
+                untracked += 1;
+                continue;
+            };
+            let rect = bb.rect(size);
+            writeln!(
+                file,
+                "{},{},{:.2},{:.2},{:.2},{:.2},1,{},1",
+                i + 1,
+                track + 1,
+                rect.left(),
+                rect.top(),
+                rect.width(),
+                rect.height(),
+                bb.class_id() + 1,
+            )?;
+            boxes += 1;
+        }
+    }
+    println!("Exported {boxes} boxes to {out:?}");
+    if untracked > 0 {
+        println!("Skipped {untracked} boxes without a track id");
+    }
+    Ok(())
+}
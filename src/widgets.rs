@@ -0,0 +1,137 @@
+use eframe::egui;
+use egui::*;
+
+use std::collections::HashMap;
+
+use crate::dataset::{Dataset, Label};
+use crate::keymap::Bindings;
+
+/// Human readable form of a key sequence from `Label::shortcuts`.
+pub fn shortcut_text(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| {
+            let name = format!("{key:?}");
+            name.strip_prefix("Num").unwrap_or(&name).to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Small filled square in the given color, e.g. for a class legend.
+pub fn color_swatch(ui: &mut Ui, color: Color32) -> Response {
+    let size = Vec2::splat(ui.spacing().interact_size.y * 0.8);
+    let (rect, response) = ui.allocate_exact_size(size, Sense::hover());
+    ui.painter().rect_filled(rect, Rounding::same(2.0), color);
+    response
+}
+
+const MAX_SEARCH_RESULTS: usize = 10;
+
+/// Progress bar over the dataset, clicking on it returns the index of the image at that position.
+pub fn seek_bar(ui: &mut Ui, current: usize, max: usize) -> Option<usize> {
+    let response = ui
+        .add(
+            ProgressBar::new(current as f32 / max as f32)
+                .show_percentage()
+                .text(format!("{current} out of {max} images")),
+        )
+        .interact(Sense::click_and_drag());
+    let index_at = |pos: Pos2| {
+        let fraction = (pos.x - response.rect.left()) / response.rect.width();
+        let i = (fraction.clamp(0.0, 1.0) * max as f32) as usize;
+        i.min(max.saturating_sub(1))
+    };
+    if let Some(pos) = response.hover_pos() {
+        response
+            .clone()
+            .on_hover_text_at_pointer(format!("Go to image {}", index_at(pos)));
+    }
+    if response.clicked() || response.drag_released() {
+        response.interact_pointer_pos().map(index_at)
+    } else {
+        None
+    }
+}
+
+/// Text field that fuzzy searches the image filenames, returns the index of the chosen result.
+pub fn search_box<L: Label>(
+    ui: &mut Ui,
+    query: &mut String,
+    dataset: &Dataset<L>,
+) -> Option<usize> {
+    let response = ui.horizontal(|ui| {
+        ui.label("Search:");
+        ui.text_edit_singleline(query)
+    });
+    // the text field gives up the focus before the actions are handled, so without taking the
+    // Enter away here it would also run the action bound to it, e.g. advance or accept
+    let enter_pressed =
+        response.inner.lost_focus() && ui.input_mut().consume_key(Modifiers::NONE, Key::Enter);
+    if query.is_empty() {
+        return None;
+    }
+    let results = dataset.search(query);
+    let mut chosen = if enter_pressed {
+        results.first().map(|(i, _)| *i)
+    } else {
+        None
+    };
+    for (i, name) in results.iter().take(MAX_SEARCH_RESULTS) {
+        if ui.selectable_label(false, format!("{i}: {name}")).clicked() {
+            chosen = Some(*i);
+        }
+    }
+    if results.len() > MAX_SEARCH_RESULTS {
+        ui.label(format!("… {} more", results.len() - MAX_SEARCH_RESULTS));
+    }
+    if results.is_empty() {
+        ui.label("No matching images");
+    }
+    if chosen.is_some() {
+        query.clear();
+    }
+    chosen
+}
+
+/// Overlay listing the key bindings of the actions and classes, toggled with `?`.
+pub fn cheat_sheet<L: Label>(
+    ctx: &Context,
+    open: &mut bool,
+    bindings: &Bindings,
+    shortcuts: &HashMap<Vec<Key>, L>,
+) {
+    let mut class_shortcuts: Vec<_> = shortcuts.iter().collect();
+    class_shortcuts.sort_by_key(|(keys, class)| (class.to_usize(), keys.len()));
+    egui::Window::new("Key bindings")
+        .open(open)
+        .collapsible(false)
+        .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+        .show(ctx, |ui| {
+            egui::Grid::new("action bindings")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (action, keys) in bindings.iter() {
+                        ui.label(action.description());
+                        let keys: Vec<_> = keys.iter().map(|key| shortcut_text(&[*key])).collect();
+                        ui.monospace(keys.join(", "));
+                        ui.end_row();
+                    }
+                    ui.label("Show or hide key bindings");
+                    ui.monospace("?");
+                    ui.end_row();
+                });
+            ui.separator();
+            ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                egui::Grid::new("class bindings")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (keys, class) in class_shortcuts {
+                            color_swatch(ui, class.color());
+                            ui.label(class.to_name());
+                            ui.monospace(shortcut_text(keys));
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+}